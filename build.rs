@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto");
+
+    // protox compiles the proto in pure Rust, so building doesn't need `protoc`.
+    #[cfg(feature = "grpc")]
+    {
+        let file_descriptors = protox::compile(["proto/miner_reports.proto"], ["proto"])?;
+        tonic_build::configure()
+            .build_client(false)
+            .compile_fds(file_descriptors)?;
+    }
+    Ok(())
+}
@@ -7,14 +7,20 @@ use axum::{
     routing::{get, post},
 };
 use clap::Parser;
-use futures::future;
+use futures::{StreamExt, future, stream};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::num::NonZeroUsize;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{RwLock, mpsc, oneshot, watch};
-use tracing::{Level, error, info, warn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::{RwLock, Semaphore, mpsc, oneshot, watch};
+use tracing::{Level, debug, error, info, warn};
 use tracing_subscriber::FmtSubscriber;
 
 #[derive(Parser, Debug)]
@@ -22,6 +28,32 @@ use tracing_subscriber::FmtSubscriber;
 struct Cli {
     #[arg(short, long, default_value_t = 300)]
     expiration_secs: u64,
+
+    /// Maximum number of pool actors queried at once on each aggregation tick.
+    #[arg(long, default_value_t = NonZeroUsize::new(64).unwrap())]
+    aggregation_concurrency: NonZeroUsize,
+
+    /// Maximum number of connections served at once; further connections are closed
+    /// as soon as they are accepted.
+    #[arg(long, default_value_t = NonZeroUsize::new(1024).unwrap())]
+    max_connections: NonZeroUsize,
+
+    /// Seconds a client gets to send the full request headers before its connection
+    /// is closed.
+    #[arg(long, default_value_t = 10)]
+    header_read_timeout_secs: u64,
+
+    /// Milliseconds the aggregator waits for the pool actors' stats on each tick, for
+    /// all of them together, before falling back to the last known stats of those that
+    /// haven't replied. Kept below the 1-second tick so slow pools can't hold up the
+    /// next snapshot.
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u64).range(1..1000))]
+    pool_reply_timeout_ms: u64,
+
+    /// Log a warning for every pool actor taking longer than this many milliseconds to
+    /// reply with its stats.
+    #[arg(long, default_value_t = 100)]
+    slow_reply_warn_ms: u64,
 }
 
 static STATS_RESPONSE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
@@ -55,13 +87,47 @@ pub struct AllStats {
     pools: BTreeMap<String, PoolStats>,
 }
 
-#[derive(Debug)]
-enum PoolActorCommand {
-    AddReport(Report),
-    CalculateStats(oneshot::Sender<PoolStats>),
+/// The channels of one pool actor. Stats requests get their own channel, which the
+/// actor always serves first, so they don't queue up behind a flood of reports.
+#[derive(Clone)]
+struct PoolActorHandle {
+    report_tx: mpsc::Sender<Report>,
+    stats_tx: mpsc::Sender<oneshot::Sender<PoolStats>>,
 }
 
-type ActorRegistry = RwLock<HashMap<String, mpsc::Sender<PoolActorCommand>>>;
+impl PoolActorHandle {
+    fn spawn(expiration_secs: u64) -> Self {
+        let (report_tx, report_rx) = mpsc::channel(256);
+        // One outstanding request per actor: while an earlier tick's request is still
+        // unanswered, the next one counts as missed instead of queueing up.
+        let (stats_tx, stats_rx) = mpsc::channel(1);
+        tokio::spawn(pool_actor(report_rx, stats_rx, expiration_secs));
+        Self {
+            report_tx,
+            stats_tx,
+        }
+    }
+}
+
+/// Limits on how long the aggregator waits for pool actors on each tick.
+#[derive(Clone, Copy)]
+struct ReplyDeadlines {
+    // For every reply of a tick together, not per pool.
+    timeout: Duration,
+    slow_warn: Duration,
+}
+
+/// What a pool actor answered to `CalculateStats` on one tick.
+enum PoolReply {
+    Stats(PoolStats),
+    /// Didn't reply before the tick's deadline, dropped the reply, or hadn't answered
+    /// the previous tick yet.
+    Missed,
+    /// The actor is gone and should be removed from the registry.
+    Dead,
+}
+
+type ActorRegistry = RwLock<HashMap<String, PoolActorHandle>>;
 
 #[derive(Clone)]
 struct AppState {
@@ -76,18 +142,12 @@ async fn post_report(
 ) -> impl IntoResponse {
     let mut registry = state.actor_registry.write().await;
 
-    let actor_tx = registry.entry(report.pool.clone()).or_insert_with(|| {
+    let actor = registry.entry(report.pool.clone()).or_insert_with(|| {
         info!("Spawning new actor for pool: {}", report.pool);
-        let (tx, rx) = mpsc::channel(256);
-        tokio::spawn(pool_actor(rx, state.expiration_secs));
-        tx
+        PoolActorHandle::spawn(state.expiration_secs)
     });
 
-    if actor_tx
-        .send(PoolActorCommand::AddReport(report))
-        .await
-        .is_err()
-    {
+    if actor.report_tx.send(report).await.is_err() {
         error!("Report channel is closed. This is a critical internal error.");
         return StatusCode::INTERNAL_SERVER_ERROR;
     }
@@ -103,15 +163,18 @@ async fn get_stats(State(state): State<AppState>) -> impl IntoResponse {
 }
 
 /// An actor that manages the data and computes stats for a single pool.
-async fn pool_actor(mut command_rx: mpsc::Receiver<PoolActorCommand>, expiration_secs: u64) {
+/// Pending stats requests are served before any queued report.
+async fn pool_actor(
+    mut report_rx: mpsc::Receiver<Report>,
+    mut stats_rx: mpsc::Receiver<oneshot::Sender<PoolStats>>,
+    expiration_secs: u64,
+) {
     let mut reports: VecDeque<Report> = VecDeque::new();
 
-    while let Some(command) = command_rx.recv().await {
-        match command {
-            PoolActorCommand::AddReport(report) => {
-                reports.push_back(report);
-            }
-            PoolActorCommand::CalculateStats(reply_tx) => {
+    loop {
+        tokio::select! {
+            biased;
+            Some(reply_tx) = stats_rx.recv() => {
                 // Step 1: Prune old reports based on the current time.
                 let now_ts = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
@@ -140,8 +203,16 @@ async fn pool_actor(mut command_rx: mpsc::Receiver<PoolActorCommand>, expiration
                 };
 
                 // Step 3: Send the small, final PoolStats struct back.
-                reply_tx.send(pool_stats).ok();
+                // The aggregator gives up after `--pool-reply-timeout-ms`; late stats
+                // are discarded.
+                if reply_tx.send(pool_stats).is_err() {
+                    debug!("Aggregator stopped waiting, discarding late stats");
+                }
             }
+            report = report_rx.recv() => match report {
+                Some(report) => reports.push_back(report),
+                None => break,
+            },
         }
     }
     info!("Pool actor shutting down as its channel was closed.");
@@ -151,8 +222,12 @@ async fn pool_actor(mut command_rx: mpsc::Receiver<PoolActorCommand>, expiration
 async fn stats_aggregator_actor(
     actor_registry: Arc<ActorRegistry>,
     stats_tx: watch::Sender<String>,
+    aggregation_concurrency: NonZeroUsize,
+    deadlines: ReplyDeadlines,
 ) {
     let mut interval = tokio::time::interval(Duration::from_secs(1));
+    // The latest stats each pool replied with, used when it misses a reply deadline.
+    let mut last_known: HashMap<String, PoolStats> = HashMap::new();
 
     loop {
         interval.tick().await;
@@ -172,49 +247,89 @@ async fn stats_aggregator_actor(
 
         // Create a copy of the necessary data (pool names and senders)
         // so we can release the lock as quickly as possible.
-        let actors_to_query: Vec<(String, mpsc::Sender<PoolActorCommand>)> = registry_lock
+        let actors_to_query: Vec<(String, PoolActorHandle)> = registry_lock
             .iter()
-            .map(|(pool_name, sender)| (pool_name.clone(), sender.clone()))
+            .map(|(pool_name, actor)| (pool_name.clone(), actor.clone()))
             .collect();
 
         // Release the read lock. Now other tasks can access the registry.
         drop(registry_lock);
 
-        // Phase 2: Asynchronously query all collected actors
-        let mut reply_channels = vec![];
-        let mut pool_names_for_replies = vec![];
-        let mut dead_pools_to_remove = vec![];
+        let pool_names: Vec<String> = actors_to_query
+            .iter()
+            .map(|(pool_name, _)| pool_name.clone())
+            .collect();
 
-        for (pool_name, actor_tx) in actors_to_query {
-            let (reply_tx, reply_rx) = oneshot::channel();
-            let command = PoolActorCommand::CalculateStats(reply_tx);
+        // Phase 2: Query the actors, at most `aggregation_concurrency` at a time, so
+        // thousands of pools don't mean thousands of in-flight replies on every tick.
+        // The whole collection shares one deadline: with a per-reply one, slow pools
+        // queued behind each other could take several timeouts between them.
+        // Pools that haven't replied, or weren't even asked, by then count as missed.
+        let mut replies: HashMap<String, PoolReply> = stream::iter(actors_to_query)
+            .map(|(pool_name, actor)| async move {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                match actor.stats_tx.try_send(reply_tx) {
+                    Ok(()) => {}
+                    // Still busy with the request from an earlier tick.
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        return (pool_name, PoolReply::Missed);
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        return (pool_name, PoolReply::Dead);
+                    }
+                }
+                let started = Instant::now();
+                let reply = match reply_rx.await {
+                    Ok(pool_stats) => PoolReply::Stats(pool_stats),
+                    Err(_) => PoolReply::Missed,
+                };
+                // Missed deadlines are reported by the caller; this catches replies that
+                // made it, but only just.
+                let latency = started.elapsed();
+                if matches!(reply, PoolReply::Stats(_)) && latency > deadlines.slow_warn {
+                    warn!(
+                        pool = %pool_name,
+                        latency_ms = latency.as_millis() as u64,
+                        "Pool actor was slow to reply with its stats"
+                    );
+                }
+                (pool_name, reply)
+            })
+            .buffer_unordered(aggregation_concurrency.get())
+            .take_until(tokio::time::sleep(deadlines.timeout))
+            .collect()
+            .await;
+
+        // Phase 3: Sort the replies into the snapshot.
+        let mut final_pools = BTreeMap::new();
+        let mut dead_pools_to_remove = vec![];
 
-            if actor_tx.send(command).await.is_err() {
-                // The actor's channel is closed. Mark it for removal later.
-                dead_pools_to_remove.push(pool_name);
-            } else {
-                reply_channels.push(reply_rx);
-                pool_names_for_replies.push(pool_name);
+        for pool_name in pool_names {
+            let reply = replies.remove(&pool_name).unwrap_or(PoolReply::Missed);
+            match reply {
+                PoolReply::Stats(pool_stats) => {
+                    last_known.insert(pool_name.clone(), pool_stats.clone());
+                    final_pools.insert(pool_name, pool_stats);
+                }
+                // Keep the previous stats rather than letting the pool vanish from
+                // this snapshot.
+                PoolReply::Missed => {
+                    warn!(pool = %pool_name, "Pool actor didn't reply in time, using last known stats");
+                    if let Some(pool_stats) = last_known.get(&pool_name) {
+                        final_pools.insert(pool_name, pool_stats.clone());
+                    }
+                }
+                PoolReply::Dead => dead_pools_to_remove.push(pool_name),
             }
         }
 
-        // Phase 2a: Clean up dead actors. This requires a write lock.
+        // Phase 3a: Clean up dead actors. This requires a write lock.
         if !dead_pools_to_remove.is_empty() {
             let mut write_lock = actor_registry.write().await;
             for pool_name in dead_pools_to_remove {
                 warn!("Removing dead actor for pool: {}", &pool_name);
                 write_lock.remove(&pool_name);
-            }
-        }
-
-        // Phase 3: Wait for all live actors to reply
-        let all_replies = future::join_all(reply_channels).await;
-        let mut final_pools = BTreeMap::new();
-
-        for (pool_name, reply_result) in pool_names_for_replies.into_iter().zip(all_replies) {
-            // reply_result is a Result from the oneshot channel receive.
-            if let Ok(pool_stats) = reply_result {
-                final_pools.insert(pool_name, pool_stats);
+                last_known.remove(&pool_name);
             }
         }
 
@@ -226,6 +341,80 @@ async fn stats_aggregator_actor(
     }
 }
 
+/// Back-off after a failed `accept`, which usually means the process is out of file
+/// descriptors; retrying immediately would just spin.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serves `app` like `axum::serve`, which can neither cap connections nor time out
+/// slow request headers. Connections over `max_connections` are closed right after
+/// accept instead of piling up, and clients that dribble their headers in for longer
+/// than `header_read_timeout` are disconnected. Returns once `shutdown` resolves and
+/// every open connection has finished.
+///
+/// Each binary in `src/bin` is a self-contained take on the same server and there
+/// is no library crate, so this has a copy in every server binary; change them
+/// together.
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    max_connections: NonZeroUsize,
+    header_read_timeout: Duration,
+    shutdown: impl Future<Output = ()>,
+) {
+    let permits = Arc::new(Semaphore::new(max_connections.get()));
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            // Dropping the stream closes it, so the client finds out right away.
+            debug!(%remote_addr, "Connection limit reached, rejecting connection");
+            continue;
+        };
+
+        let connection = builder
+            .serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(app.clone()),
+            )
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!(%remote_addr, error = %err, "Connection closed with an error");
+            }
+            drop(permit);
+        });
+    }
+
+    graceful.shutdown().await;
+}
+
+/// The JSON `/stats` serves until the first snapshot is published.
+fn initial_stats_json() -> String {
+    serde_json::to_string(&AllStats::default()).unwrap_or_else(|err| {
+        error!(error = %err, "Failed to serialize the initial stats, serving an empty object");
+        "{}".to_string()
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let subscriber = FmtSubscriber::builder()
@@ -235,10 +424,18 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
     let actor_registry = Arc::new(RwLock::new(HashMap::new()));
-    let (stats_tx, stats_rx) = watch::channel(serde_json::to_string(&AllStats::default()).unwrap());
+    let (stats_tx, stats_rx) = watch::channel(initial_stats_json());
 
     info!("Spawning stats aggregator actor...");
-    tokio::spawn(stats_aggregator_actor(actor_registry.clone(), stats_tx));
+    tokio::spawn(stats_aggregator_actor(
+        actor_registry.clone(),
+        stats_tx,
+        cli.aggregation_concurrency,
+        ReplyDeadlines {
+            timeout: Duration::from_millis(cli.pool_reply_timeout_ms),
+            slow_warn: Duration::from_millis(cli.slow_reply_warn_ms),
+        },
+    ));
 
     let app_state = AppState {
         actor_registry,
@@ -253,8 +450,109 @@ async fn main() -> Result<()> {
 
     let addr = "127.0.0.1:3000";
     info!("Server listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    let listener = TcpListener::bind(&addr).await?;
+    serve(
+        listener,
+        app,
+        cli.max_connections,
+        Duration::from_secs(cli.header_read_timeout_secs),
+        future::pending(),
+    )
+    .await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn aggregator_removes_actors_whose_channel_closed() {
+        let actor_registry = Arc::new(RwLock::new(HashMap::new()));
+        let (stats_tx, mut stats_rx) = watch::channel(initial_stats_json());
+
+        let alive = PoolActorHandle::spawn(300);
+        let (report_tx, _) = mpsc::channel(256);
+        let (stats_tx_dead, _) = mpsc::channel(1);
+        let dead = PoolActorHandle {
+            report_tx,
+            stats_tx: stats_tx_dead,
+        };
+        {
+            let mut registry = actor_registry.write().await;
+            registry.insert("alive".to_string(), alive);
+            registry.insert("dead".to_string(), dead);
+        }
+
+        tokio::spawn(stats_aggregator_actor(
+            actor_registry.clone(),
+            stats_tx,
+            NonZeroUsize::new(4).unwrap(),
+            ReplyDeadlines {
+                timeout: Duration::from_millis(500),
+                slow_warn: Duration::from_millis(100),
+            },
+        ));
+
+        // The first tick fires right away and removes dead actors before publishing.
+        tokio::time::timeout(Duration::from_secs(5), stats_rx.changed())
+            .await
+            .expect("aggregator didn't publish a snapshot")
+            .unwrap();
+
+        let registry = actor_registry.read().await;
+        assert!(registry.contains_key("alive"));
+        assert!(!registry.contains_key("dead"));
+
+        let stats: serde_json::Value = serde_json::from_str(&stats_rx.borrow()).unwrap();
+        assert!(stats["pools"].get("alive").is_some());
+        assert!(stats["pools"].get("dead").is_none());
+    }
+
+    #[tokio::test]
+    async fn aggregator_waits_once_for_all_slow_actors() {
+        let actor_registry = Arc::new(RwLock::new(HashMap::new()));
+        let (stats_tx, mut stats_rx) = watch::channel(initial_stats_json());
+
+        // Actors that take requests but never answer them.
+        for pool_name in ["a", "b", "c"] {
+            let (report_tx, _) = mpsc::channel(256);
+            let (stats_tx, mut stats_rx) = mpsc::channel(1);
+            tokio::spawn(async move {
+                let mut pending = vec![];
+                while let Some(reply_tx) = stats_rx.recv().await {
+                    pending.push(reply_tx);
+                }
+            });
+            let silent = PoolActorHandle {
+                report_tx,
+                stats_tx,
+            };
+            actor_registry
+                .write()
+                .await
+                .insert(pool_name.to_string(), silent);
+        }
+
+        let started = Instant::now();
+        tokio::spawn(stats_aggregator_actor(
+            actor_registry.clone(),
+            stats_tx,
+            NonZeroUsize::new(1).unwrap(),
+            ReplyDeadlines {
+                timeout: Duration::from_millis(200),
+                slow_warn: Duration::from_millis(100),
+            },
+        ));
+
+        tokio::time::timeout(Duration::from_secs(5), stats_rx.changed())
+            .await
+            .expect("aggregator didn't publish a snapshot")
+            .unwrap();
+
+        // One at a time, three per-reply timeouts would have taken 600ms.
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert_eq!(actor_registry.read().await.len(), 3);
+    }
+}
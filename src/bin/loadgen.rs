@@ -0,0 +1,183 @@
+use anyhow::{Result, bail};
+use clap::Parser;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{Level, info, warn};
+use tracing_subscriber::FmtSubscriber;
+
+/// Fires synthetic reports at a `/report` endpoint at a fixed rate and prints the
+/// achieved throughput and latency percentiles.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "http://127.0.0.1:3000/report")]
+    url: String,
+
+    /// Target reports per second.
+    #[arg(short, long, default_value_t = 1000)]
+    rate: u64,
+
+    #[arg(short, long, default_value_t = 6)]
+    pools: usize,
+
+    #[arg(short, long, default_value_t = 100)]
+    workers: usize,
+
+    #[arg(short, long, default_value_t = 30)]
+    duration_secs: u64,
+
+    /// Maximum number of requests in flight at once.
+    #[arg(short, long, default_value_t = 256)]
+    concurrency: usize,
+
+    /// Seed for the generated values, so runs can be reproduced exactly.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+/// The wire format of `POST /report`. The servers each keep their own copy of this
+/// type, so it is mirrored here rather than shared.
+#[derive(Debug, Serialize)]
+struct Report {
+    worker_id: String,
+    pool: String,
+    hashrate: f64,
+    temperature: f64,
+    timestamp: u64,
+}
+
+/// How often the generator wakes up to send the reports that are due.
+const PACING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A tiny xorshift PRNG; good enough for synthetic telemetry and fully repeatable.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero.
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value in `[low, high)`.
+    fn next_range(&mut self, low: f64, high: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        low + unit * (high - low)
+    }
+}
+
+fn next_report(rng: &mut XorShift, cli: &Cli) -> Report {
+    let worker = rng.next_u64() as usize % cli.workers;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Report {
+        worker_id: format!("worker-{worker}"),
+        // Workers stick to one pool, like real rigs do.
+        pool: format!("pool-{}", worker % cli.pools),
+        hashrate: rng.next_range(90.0, 110.0),
+        temperature: rng.next_range(60.0, 80.0),
+        timestamp,
+    }
+}
+
+/// Nearest-rank percentile of an already sorted slice.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    let cli = Cli::parse();
+    if cli.pools == 0 || cli.workers == 0 || cli.rate == 0 || cli.concurrency == 0 {
+        bail!("--rate, --pools, --workers and --concurrency must all be greater than 0");
+    }
+    info!(config = ?cli, "Load generator starting with configuration");
+
+    let client = reqwest::Client::new();
+    let permits = Arc::new(Semaphore::new(cli.concurrency));
+    let (result_tx, mut result_rx) = mpsc::unbounded_channel::<Option<Duration>>();
+    let mut rng = XorShift::new(cli.seed);
+
+    let duration = Duration::from_secs(cli.duration_secs);
+    let mut pacing = tokio::time::interval(PACING_INTERVAL);
+    let started = Instant::now();
+    let mut sent: u64 = 0;
+
+    while started.elapsed() < duration {
+        pacing.tick().await;
+
+        // Send whatever is due by now, which also catches up after a late tick.
+        let due = (started.elapsed().as_secs_f64() * cli.rate as f64) as u64;
+        while sent < due {
+            // Waiting here means the server can't keep up with the target rate.
+            let permit = permits.clone().acquire_owned().await?;
+            let request = client.post(&cli.url).json(&next_report(&mut rng, &cli));
+            let result_tx = result_tx.clone();
+
+            tokio::spawn(async move {
+                let request_started = Instant::now();
+                let ok =
+                    matches!(request.send().await, Ok(response) if response.status().is_success());
+                let latency = request_started.elapsed();
+                drop(permit);
+                result_tx.send(ok.then_some(latency)).ok();
+            });
+            sent += 1;
+        }
+    }
+
+    // Every in-flight request holds a sender, so this completes once they finish.
+    drop(result_tx);
+    let mut latencies = Vec::with_capacity(sent as usize);
+    let mut errors: u64 = 0;
+    while let Some(result) = result_rx.recv().await {
+        match result {
+            Some(latency) => latencies.push(latency),
+            None => errors += 1,
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    if errors > 0 {
+        warn!(errors, "Some requests failed");
+    }
+
+    latencies.sort_unstable();
+    println!("target rate:   {} reports/s", cli.rate);
+    println!(
+        "achieved rate: {:.1} reports/s ({} ok, {} failed in {:.1}s)",
+        latencies.len() as f64 / elapsed,
+        latencies.len(),
+        errors,
+        elapsed
+    );
+    for pct in [50.0, 90.0, 95.0, 99.0] {
+        println!("p({pct}):        {:?}", percentile(&latencies, pct));
+    }
+    println!(
+        "max:           {:?}",
+        latencies.last().copied().unwrap_or_default()
+    );
+
+    Ok(())
+}
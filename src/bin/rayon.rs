@@ -8,14 +8,21 @@ use axum::{
 };
 use clap::Parser;
 use crossbeam_queue::SegQueue;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::watch;
-use tracing::{Level, info};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::{Semaphore, watch};
+use tracing::{Level, debug, error, info, warn};
 use tracing_subscriber::FmtSubscriber;
 
 #[derive(Parser, Debug)]
@@ -23,6 +30,28 @@ use tracing_subscriber::FmtSubscriber;
 struct Cli {
     #[arg(short, long, default_value_t = 300)]
     expiration_secs: u64,
+
+    /// Most reports taken off the queue per tick. Under sustained overload the rest
+    /// wait for the next tick instead of making this one run long, and a warning is
+    /// logged while the backlog grows.
+    #[arg(long, default_value_t = 1_000_000)]
+    max_drain_per_tick: usize,
+
+    /// Every this many seconds, give back the memory the aggregator's pool map and
+    /// report deques grew to hold at their peak, so pools that appeared in a burst
+    /// and went silent don't keep it for the life of the process.
+    #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    compaction_interval_secs: u64,
+
+    /// Maximum number of connections served at once; further connections are closed
+    /// as soon as they are accepted.
+    #[arg(long, default_value_t = NonZeroUsize::new(1024).unwrap())]
+    max_connections: NonZeroUsize,
+
+    /// Seconds a client gets to send the full request headers before its connection
+    /// is closed.
+    #[arg(long, default_value_t = 10)]
+    header_read_timeout_secs: u64,
 }
 
 static STATS_RESPONSE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
@@ -85,22 +114,47 @@ async fn stats_aggregator_actor(
     report_queue: Arc<ReportQueue>,
     stats_tx: watch::Sender<String>,
     expiration_secs: u64,
+    max_drain_per_tick: usize,
+    compaction_interval: Duration,
+    mut shutdown_rx: watch::Receiver<bool>,
 ) {
     let mut interval = tokio::time::interval(Duration::from_secs(1));
     // This is the aggregator's own persistent state.
     let mut pool_data: HashMap<String, VecDeque<Report>> = HashMap::new();
+    // Reports left in the queue after the previous drain.
+    let mut previous_backlog = 0;
+    let mut last_compaction = Instant::now();
 
     loop {
-        interval.tick().await;
+        // Wait for the next tick or for shutdown. Either way one more pass runs below,
+        // so reports still sitting in the queue make it into a final snapshot.
+        let shutting_down = tokio::select! {
+            _ = interval.tick() => false,
+            _ = shutdown_rx.changed() => true,
+        };
 
-        // Step 1: Drain the global queue
-        let mut new_reports = Vec::with_capacity(report_queue.len());
+        // Step 1: Drain the global queue, up to the per-tick cap. The final pass takes
+        // everything, so nothing is lost on shutdown.
+        let drain_limit = if shutting_down {
+            usize::MAX
+        } else {
+            max_drain_per_tick
+        };
+        let mut new_reports = Vec::with_capacity(report_queue.len().min(drain_limit));
         // SegQueue is fantastic for concurrent writes but terrible for parallel processing because you can't easily "split" it
         // so moving the data to Vec
-        while let Some(report) = report_queue.pop() {
+        while new_reports.len() < drain_limit
+            && let Some(report) = report_queue.pop()
+        {
             new_reports.push(report);
         }
 
+        let backlog = report_queue.len();
+        if backlog > previous_backlog {
+            warn!(backlog, max_drain_per_tick, "Report backlog is growing");
+        }
+        previous_backlog = backlog;
+
         // Step 2: Parallel Grouping with Rayon. Parrallel fold/reduce do the magic here!
         let new_data_by_pool: HashMap<String, Vec<Report>> = new_reports
             .into_par_iter() // parallel iterator
@@ -133,29 +187,45 @@ async fn stats_aggregator_actor(
 
         let pools: BTreeMap<String, PoolStats> = pool_data
             .par_iter_mut() // Use a parallel mutable iterator
-            .map(|(pool_name, deque)| {
-                // This closure runs in parallel for each pool.
-                deque.retain(|r| r.timestamp >= expiration_ts);
-
-                let (total_hashrate, total_temp, unique_workers) =
-                    deque
-                        .iter()
-                        .fold((0.0, 0.0, HashSet::new()), |(h, t, mut w), r| {
-                            w.insert(&r.worker_id);
-                            (h + r.hashrate, t + r.temperature, w)
-                        });
-
-                let stats = if !deque.is_empty() {
-                    PoolStats {
+            .filter_map(|(pool_name, deque)| {
+                // This closure runs in parallel for each pool. A panic here would be
+                // propagated by rayon and take the whole tick down with it, so each
+                // pool is isolated and falls back to default stats instead.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    deque.retain(|r| r.timestamp >= expiration_ts);
+
+                    // A pool whose last reports just expired is gone: skip it rather than
+                    // publishing a zero-stat entry. Its deque is removed in Step 5.
+                    if deque.is_empty() {
+                        return None;
+                    }
+
+                    let (total_hashrate, total_temp, unique_workers) =
+                        deque
+                            .iter()
+                            .fold((0.0, 0.0, HashSet::new()), |(h, t, mut w), r| {
+                                w.insert(&r.worker_id);
+                                (h + r.hashrate, t + r.temperature, w)
+                            });
+
+                    Some(PoolStats {
                         workers: unique_workers.len(),
                         avg_hashrate: total_hashrate / deque.len() as f64,
                         avg_temp: total_temp / deque.len() as f64,
-                    }
-                } else {
-                    PoolStats::default()
-                };
+                    })
+                }));
 
-                (pool_name.clone(), stats)
+                let stats = result.unwrap_or_else(|payload| {
+                    let reason = payload
+                        .downcast_ref::<&str>()
+                        .copied()
+                        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("unknown panic");
+                    error!(pool = %pool_name, reason, "Stats calculation panicked for pool");
+                    Some(PoolStats::default())
+                });
+
+                stats.map(|stats| (pool_name.clone(), stats))
             })
             .collect(); // Rayon's .collect() builds the BTreeMap in a parallel-friendly way.
 
@@ -163,11 +233,136 @@ async fn stats_aggregator_actor(
         // This must be done in a separate, single-threaded step.
         pool_data.retain(|_, deque| !deque.is_empty());
 
+        // Step 5a: Periodically shrink the map and deques to what they hold now.
+        // Removing entries never releases capacity by itself.
+        if last_compaction.elapsed() >= compaction_interval {
+            last_compaction = Instant::now();
+            let capacity_before = pool_data.capacity();
+            pool_data.shrink_to_fit();
+            for deque in pool_data.values_mut() {
+                deque.shrink_to_fit();
+            }
+            debug!(
+                pools = pool_data.len(),
+                capacity_before,
+                capacity_after = pool_data.capacity(),
+                "Compacted aggregator state"
+            );
+        }
+
         let current_stats = AllStats { pools };
         if let Ok(json) = serde_json::to_string(&current_stats) {
             stats_tx.send(json).ok();
         }
+
+        if shutting_down {
+            info!("Final snapshot published. Stats aggregator shutting down.");
+            break;
+        }
+    }
+}
+
+/// Back-off after a failed `accept`, which usually means the process is out of file
+/// descriptors; retrying immediately would just spin.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serves `app` like `axum::serve`, which can neither cap connections nor time out
+/// slow request headers. Connections over `max_connections` are closed right after
+/// accept instead of piling up, and clients that dribble their headers in for longer
+/// than `header_read_timeout` are disconnected. Returns once `shutdown` resolves and
+/// every open connection has finished.
+///
+/// Each binary in `src/bin` is a self-contained take on the same server and there
+/// is no library crate, so this has a copy in every server binary; change them
+/// together.
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    max_connections: NonZeroUsize,
+    header_read_timeout: Duration,
+    shutdown: impl Future<Output = ()>,
+) {
+    let permits = Arc::new(Semaphore::new(max_connections.get()));
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            // Dropping the stream closes it, so the client finds out right away.
+            debug!(%remote_addr, "Connection limit reached, rejecting connection");
+            continue;
+        };
+
+        let connection = builder
+            .serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(app.clone()),
+            )
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!(%remote_addr, error = %err, "Connection closed with an error");
+            }
+            drop(permit);
+        });
+    }
+
+    graceful.shutdown().await;
+}
+
+/// Resolves on Ctrl+C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!(error = %err, "Failed to install SIGTERM handler");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
+    info!("Shutdown signal received");
+}
+
+/// The JSON `/stats` serves until the first snapshot is published.
+fn initial_stats_json() -> String {
+    serde_json::to_string(&AllStats::default()).unwrap_or_else(|err| {
+        error!(error = %err, "Failed to serialize the initial stats, serving an empty object");
+        "{}".to_string()
+    })
 }
 
 #[tokio::main]
@@ -181,13 +376,18 @@ async fn main() -> Result<()> {
     info!(config = ?cli, "Service starting with configuration");
 
     let report_queue = Arc::new(ReportQueue::new());
-    let (stats_tx, stats_rx) = watch::channel(serde_json::to_string(&AllStats::default()).unwrap());
+    let (stats_tx, stats_rx) = watch::channel(initial_stats_json());
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     info!("Spawning Rayon-powered stats aggregator actor...");
-    tokio::spawn(stats_aggregator_actor(
+    let aggregator = tokio::spawn(stats_aggregator_actor(
         report_queue.clone(),
         stats_tx,
         cli.expiration_secs,
+        cli.max_drain_per_tick,
+        Duration::from_secs(cli.compaction_interval_secs),
+        shutdown_rx,
     ));
 
     let app_state = AppState {
@@ -202,8 +402,19 @@ async fn main() -> Result<()> {
 
     let addr = "127.0.0.1:3000";
     info!("Server listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    let listener = TcpListener::bind(&addr).await?;
+    serve(
+        listener,
+        app,
+        cli.max_connections,
+        Duration::from_secs(cli.header_read_timeout_secs),
+        shutdown_signal(),
+    )
+    .await;
+
+    // The server no longer accepts reports, so the aggregator can flush and stop.
+    shutdown_tx.send(true).ok();
+    aggregator.await?;
 
     Ok(())
 }
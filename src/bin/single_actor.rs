@@ -1,27 +1,529 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use axum::{
     Json, Router,
-    extract::State,
-    http::{HeaderMap, StatusCode, header},
-    response::IntoResponse,
+    body::{Body, Bytes},
+    extract::{
+        Form, FromRequest, Path, Query, Request, State,
+        rejection::{BytesRejection, FailedToBufferBody, FormRejection, JsonRejection},
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
-use clap::Parser;
+use chrono::DateTime;
+use clap::{ArgAction, Parser, ValueEnum};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::FutureExt;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
 use once_cell::sync::Lazy;
+use serde::de::{self, Deserializer, Visitor};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{mpsc, watch};
-use tracing::{Level, error, info};
-use tracing_subscriber::FmtSubscriber;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tdigest::TDigest;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, Semaphore, broadcast, mpsc, oneshot, watch};
+use tokio::time::MissedTickBehavior;
+use tower_http::LatencyUnit;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::{Instrument, Level, debug, error, info, info_span, warn};
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use uuid::Uuid;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    #[arg(short, long, default_value_t = 300)]
+    /// How long a report counts towards the stats. At least 1, as a zero-length
+    /// window would prune every report as soon as it arrives.
+    #[arg(
+        short,
+        long,
+        default_value_t = 300,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
     expiration_secs: u64,
+
+    /// Larger --expiration-secs values are clamped to this, with a warning, since a
+    /// window that never prunes keeps every report in memory. The default is a week.
+    #[arg(
+        long,
+        default_value_t = 7 * 24 * 3600,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_expiration_secs: u64,
+
+    /// Log method, path, status and latency of every request at DEBUG level.
+    #[arg(long)]
+    access_log: bool,
+
+    /// Accept /report bodies as JSON regardless of the declared Content-Type.
+    #[arg(long)]
+    lenient_content_type: bool,
+
+    /// Bearer token required by the /admin endpoints. They are not mounted without it.
+    #[arg(long)]
+    admin_token: Option<Secret>,
+
+    /// JSON file mapping bearer tokens to their scopes, e.g.
+    /// `{"miner-token": ["write"], "dashboard-token": ["read"]}`. With it, ingest
+    /// needs a `write` token and the read endpoints a `read` one; other requests
+    /// get 403. Without it, those endpoints are open.
+    #[arg(long)]
+    auth_file: Option<PathBuf>,
+
+    /// When ingest endpoints answer. `queued` answers as soon as the report is in the
+    /// data actor's queue. `processed` waits until the data actor has added it to
+    /// its pool (503 after a timeout), trading latency for the certainty that it is
+    /// counted.
+    #[arg(long, value_enum, default_value_t = AckMode::Queued)]
+    ack_mode: AckMode,
+
+    /// Body of a successful `POST /report`. `empty` sends none. `verbose` confirms
+    /// with `{"accepted": true, "pool": ..., "server_time": ...}`: the pool after
+    /// `--default-pool` and `--normalize-pool-case`, and the server's UNIX time.
+    /// `POST /preaggregated` answers the same way; `POST /reports` always sends none.
+    #[arg(long, value_enum, default_value_t = ReportResponse::Empty)]
+    report_response: ReportResponse,
+
+    /// Maximum number of reports accepted in a single POST /reports body, and the
+    /// most reports a POST /preaggregated batch can stand for.
+    #[arg(long, default_value_t = 10_000)]
+    max_batch_size: usize,
+
+    /// Upper bound for a report's own `ttl_secs`, so clients can't pin reports forever.
+    #[arg(long, default_value_t = 3600)]
+    max_ttl_secs: u64,
+
+    /// Push the metrics to this Prometheus Pushgateway (e.g. http://host:9091) on every tick.
+    #[arg(long)]
+    pushgateway_url: Option<String>,
+
+    /// Hide a pool from the stats until it has received at least this many reports,
+    /// so a single stray report doesn't create a phantom pool.
+    #[arg(long, default_value_t = 1)]
+    min_reports_to_appear: u64,
+
+    /// Pool for reports that don't name one. Without it such reports are rejected
+    /// with 400.
+    #[arg(long)]
+    default_pool: Option<String>,
+
+    /// Fold pool names to one case so that e.g. "Pool-A" and "pool-a" are merged.
+    #[arg(long, value_enum, default_value_t = PoolCase::None)]
+    normalize_pool_case: PoolCase,
+
+    /// Also compute the fields that need sorting each pool's reports (e.g. `median_temp`).
+    #[arg(long)]
+    percentiles: bool,
+
+    /// How --percentiles are computed. `exact` sorts every pool's reports on each
+    /// tick. `tdigest` keeps a t-digest per pool and time bucket, updated as reports
+    /// arrive, which costs bounded memory and no sort but is approximate, also in
+    /// which reports count: a bucket is dropped once its newest report has expired,
+    /// ignoring `ttl_secs`. Regions get no percentiles in this mode.
+    #[arg(long, value_enum, default_value_t = PercentileMode::Exact, requires = "percentiles")]
+    percentile_mode: PercentileMode,
+
+    /// Trim surrounding whitespace from worker IDs, so "  rig1 " and "rig1" are one worker.
+    #[arg(long)]
+    trim_worker_id: bool,
+
+    /// Also lowercase worker IDs. Only applies together with --trim-worker-id.
+    #[arg(long, requires = "trim_worker_id")]
+    lowercase_worker_id: bool,
+
+    /// Clock that report expiration is measured against. `newest` uses the newest
+    /// report timestamp seen for the pool, which keeps pools with a skewed clock
+    /// coherent, but a pool that stops reporting then keeps its last window.
+    #[arg(long, value_enum, default_value_t = ExpireRelativeTo::Server)]
+    expire_relative_to: ExpireRelativeTo,
+
+    /// What ingest does with a report that has already expired when it arrives,
+    /// going by its timestamp and TTL: `reject` answers 400, `drop` accepts and
+    /// discards it. Both are counted in `/metrics`. Only checked with sliding
+    /// windows and `--expire-relative-to server`, where expiry is known on arrival.
+    #[arg(long, value_enum, default_value_t = ExpiredReports::Reject)]
+    expired_reports: ExpiredReports,
+
+    /// `sliding` reports stats over the last --expiration-secs, updated every second.
+    /// `tumbling` cuts time into fixed --expiration-secs windows aligned to the UNIX
+    /// epoch (e.g. whole minutes) and publishes each window's stats once it ends,
+    /// then starts the next one. Tumbling windows line up with billing periods but
+    /// `/stats` is up to a window old, and reports count towards the window they
+    /// arrive in, ignoring `ttl_secs` and --expire-relative-to. Only a report stamped
+    /// in the next window that arrives before this one is published goes to the next.
+    #[arg(long, value_enum, default_value_t = WindowMode::Sliding)]
+    window_mode: WindowMode,
+
+    /// Drop expired reports only this often rather than on every recalculation.
+    /// Each prune walks every retained report, so with a long window, where little
+    /// expires per second, a longer interval saves most of that CPU. The price is
+    /// that stats published between prunes still include reports that expired up
+    /// to this many seconds ago.
+    #[arg(long, default_value_t = 1)]
+    prune_interval_secs: u64,
+
+    /// Most queued reports the data actor ingests in a row before it checks the
+    /// recalculation timer again. A due recalculation always goes before further
+    /// reports, so under sustained ingest stats still publish at most this many
+    /// reports late. Larger batches ingest with less overhead.
+    #[arg(long, default_value_t = NonZeroUsize::new(256).unwrap())]
+    max_reports_per_iteration: NonZeroUsize,
+
+    /// Keep running sums and a per-worker report count up to date as reports arrive
+    /// and expire, per pool and per region, instead of folding every retained
+    /// report on each tick, and keep each pool's reports in expiry order so pruning
+    /// only touches the expired ones. Fields that need a full scan
+    /// (`oldest_report_age_secs`, `max_temp`, the report intervals,
+    /// `healthy_worker_pct` and `zero_hashrate_workers`) are left out of the stats in this mode.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "percentiles",
+            "per_second_dedup",
+            "max_reports_per_worker_per_window",
+            "exclude_zero_hashrate",
+            "avg_window_secs",
+            "interruption_gap_secs",
+            "temp_median_window",
+        ]
+    )]
+    incremental_stats: bool,
+
+    /// With --incremental-stats, rebuild the running sums from the retained reports
+    /// every this many ticks, bounding the float error that adding and subtracting
+    /// accumulates.
+    #[arg(long, default_value_t = 60)]
+    incremental_resync_ticks: u64,
+
+    /// Count only the last received report of a worker within the same timestamp
+    /// second, instead of every one. Applied when the stats are calculated.
+    #[arg(long)]
+    per_second_dedup: bool,
+
+    /// Leave reports of exactly 0 hashrate (idle or just started rigs) out of
+    /// `avg_hashrate`. Their workers still count towards `workers`, and
+    /// `zero_hashrate_workers` tells how many of them are idle.
+    #[arg(long)]
+    exclude_zero_hashrate: bool,
+
+    /// Compute `avg_hashrate` and `avg_temp` over just the reports of the last this
+    /// many seconds, by the server clock, while the other stats keep covering the
+    /// whole --expiration-secs window. At most --expiration-secs. Both averages are
+    /// 0 while a pool has no report that recent. `pool_hashrate`, and so
+    /// --hashrate-floor, likewise only counts workers whose latest report is that
+    /// recent.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    avg_window_secs: Option<u64>,
+
+    /// Guard against sensor glitches (a single 0 or 255 reading): each report's
+    /// temperature counts towards `avg_temp` and `sum_temp` as the median of its
+    /// worker's last this many temperatures up to it, in timestamp order. A worker's
+    /// first readings, which have fewer before them, take the median of its first
+    /// this many instead, so a glitch in its very first report is caught too. At
+    /// least 3, so a single glitch is always outvoted; a worker with fewer than 3
+    /// readings retained has them counted as they are. The temperature percentiles
+    /// still see the raw readings.
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(3..))]
+    temp_median_window: Option<usize>,
+
+    /// Count at most this many of each worker's most recently received reports in a
+    /// pool's stats, so a worker reporting far more often than the rest can't
+    /// dominate the averages. Unlimited by default.
+    #[arg(long)]
+    max_reports_per_worker_per_window: Option<NonZeroUsize>,
+
+    /// A worker counts towards `healthy_worker_pct` if its latest report is at most
+    /// this old. Meant to be shorter than --expiration-secs.
+    #[arg(long, default_value_t = 60)]
+    healthy_within_secs: u64,
+
+    /// Mark a pool as `interrupted` in `/stats` while the longest gap between two
+    /// consecutive reports in its window, e.g. from a network outage, is longer than
+    /// this. Its averages then span disjoint stretches of time.
+    #[arg(long)]
+    interruption_gap_secs: Option<u64>,
+
+    /// Mark a pool as `temp_alerting` in `/stats` while its `avg_temp` is above this.
+    /// The alert is logged when it fires and when it resolves.
+    #[arg(long)]
+    temp_alert_threshold: Option<f64>,
+
+    /// Per-pool override of --temp-alert-threshold, as `POOL=THRESHOLD`. Repeatable.
+    #[arg(long = "pool-temp-alert-threshold", value_parser = parse_pool_threshold)]
+    pool_temp_alert_thresholds: Vec<(String, f64)>,
+
+    /// Mark a pool as `hashrate_alerting` in `/stats` while its `pool_hashrate`, the
+    /// sum of its workers' latest hashrates, is below this, e.g. after mass
+    /// disconnects or when most rigs go idle.
+    /// Like the temperature alert, it is logged when it fires and when it resolves.
+    #[arg(long)]
+    hashrate_floor: Option<f64>,
+
+    /// Per-pool override of --hashrate-floor, as `POOL=FLOOR`. Repeatable.
+    #[arg(long = "pool-hashrate-floor", value_parser = parse_pool_threshold)]
+    pool_hashrate_floors: Vec<(String, f64)>,
+
+    /// Field names of the pools in `/stats`. `legacy` renames `avg_hashrate` and
+    /// `avg_temp` to `hashrate` and `temp`, for dashboards that still expect those.
+    #[arg(long, value_enum, default_value_t = OutputProfile::Default)]
+    output_profile: OutputProfile,
+
+    /// Decimal places `avg_temp` is rounded to; sensors don't resolve beyond that.
+    #[arg(long, default_value_t = 1)]
+    temp_precision: u32,
+
+    /// Keep the last published stats in this file and serve them at startup until
+    /// the first fresh snapshot, instead of an empty one.
+    #[arg(long)]
+    stats_cache_path: Option<PathBuf>,
+
+    /// Also print every published snapshot to stdout, as one line of `/stats` JSON
+    /// per tick, for a parent process reading it through a pipe. Logs go to stderr
+    /// instead, so they don't mix in.
+    #[arg(long)]
+    emit_stdout: bool,
+
+    /// Ignore a --stats-cache-path snapshot generated longer ago than this, as every
+    /// report behind it has expired since. Defaults to --expiration-secs.
+    #[arg(long, requires = "stats_cache_path")]
+    max_stats_cache_age_secs: Option<u64>,
+
+    /// Also append every published snapshot to the `pool_stats` table of this SQLite
+    /// database, for long-term querying.
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    sqlite_path: Option<PathBuf>,
+
+    /// Longest pool name accepted, in bytes; longer reports are rejected with 400.
+    #[arg(long, default_value_t = 128)]
+    max_pool_name_len: usize,
+
+    /// Longest worker ID accepted, in bytes; longer reports are rejected with 400.
+    #[arg(long, default_value_t = 128)]
+    max_worker_id_len: usize,
+
+    /// Whether pools whose reports have all expired still appear in `/stats`, with
+    /// zeroed stats, so it is visible that they exist.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    show_empty_pools: bool,
+
+    /// Once all of a pool's reports have expired, keep showing its last stats,
+    /// marked `stale`, until this long after its last report, then remove it from
+    /// `/stats`. Takes precedence over --show-empty-pools.
+    #[arg(long)]
+    retain_pool_secs: Option<u64>,
+
+    /// Only publish a snapshot when it differs from the previous one, ignoring
+    /// `generated_at`, which then tells when the stats last changed. While reports
+    /// keep arriving `reports_per_sec` and the report ages usually change anyway,
+    /// so this mostly quiets subscribers of idle or stable deployments.
+    #[arg(long)]
+    publish_on_change: bool,
+
+    /// Publish each pool's stats as this weight (0 to 1] of the new values plus the
+    /// rest of the previously published ones, to keep dashboards from flickering on
+    /// jittery data. Applies to every fractional field, while counts, flags and the
+    /// region rollups stay instantaneous. Alerts fire on the smoothed values. Without
+    /// it, each snapshot replaces the last.
+    #[arg(long, value_parser = parse_smoothing_weight)]
+    publish_smoothing: Option<f64>,
+
+    /// With more pools than this, `/stats` only returns this many with the highest
+    /// `sum_hashrate`, plus `"truncated": true` and the `total_pools` count (a
+    /// `X-Total-Pools` header with `?format=array`). Keeps the response small on
+    /// deployments with thousands of pools. The other endpoints see every pool.
+    #[arg(long)]
+    max_pools_in_response: Option<NonZeroUsize>,
+
+    /// Feed the reports of this JSONL file (one `Report` per line) through the normal
+    /// ingest path on startup, e.g. to reproduce a bug from captured traffic.
+    /// Reports older than the window are rejected or dropped as expired unless
+    /// combined with `--expire-relative-to newest`.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Replay at this multiple of the speed the reports were originally sent at,
+    /// going by their timestamps. Without it the file is replayed as fast as possible.
+    #[arg(long, requires = "replay")]
+    replay_speed: Option<f64>,
+
+    /// Serve everything but the ingest endpoints (/report, /reports, /report/ws) on
+    /// this separate address, e.g. an internal-only port, leaving only ingest on the
+    /// main listener. Without it, all endpoints share the main listener.
+    #[arg(long)]
+    internal_addr: Option<SocketAddr>,
+
+    /// Also serve the gRPC API of `proto/miner_reports.proto` on this address.
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_addr: Option<SocketAddr>,
+
+    /// Also subscribe to reports on this MQTT broker, as `HOST[:PORT]` (port 1883 by
+    /// default). Each message payload is one JSON report.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_parser = mqtt::parse_broker, requires = "mqtt_topic")]
+    mqtt_broker: Option<(String, u16)>,
+
+    /// Topic filter to subscribe to on `--mqtt-broker`, wildcards included. Reports
+    /// without a pool get the topic level matched by the filter's first wildcard, so
+    /// with `miners/+/telemetry` a report on `miners/eu1/telemetry` goes to `eu1`.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_broker")]
+    mqtt_topic: Option<String>,
+
+    /// Maximum number of connections served at once; further connections are closed
+    /// as soon as they are accepted.
+    #[arg(long, default_value_t = NonZeroUsize::new(1024).unwrap())]
+    max_connections: NonZeroUsize,
+
+    /// Seconds a client gets to send the full request headers before its connection
+    /// is closed.
+    #[arg(long, default_value_t = 10)]
+    header_read_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ExpireRelativeTo {
+    Newest,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PercentileMode {
+    Exact,
+    Tdigest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputProfile {
+    Default,
+    Legacy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ExpiredReports {
+    Reject,
+    Drop,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AckMode {
+    Queued,
+    Processed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ReportResponse {
+    Empty,
+    Verbose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum WindowMode {
+    Sliding,
+    Tumbling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PoolCase {
+    Lower,
+    Upper,
+    None,
+}
+
+impl PoolCase {
+    fn apply(self, pool: String) -> String {
+        match self {
+            PoolCase::Lower => pool.to_lowercase(),
+            PoolCase::Upper => pool.to_uppercase(),
+            PoolCase::None => pool,
+        }
+    }
+}
+
+/// A CLI value that must not end up in the startup configuration log.
+#[derive(Clone)]
+struct Secret(String);
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"<redacted>\"")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+/// Address the HTTP server listens on.
+const BIND_ADDR: &str = "127.0.0.1:3000";
+
+/// How often the data actor recalculates and publishes the stats.
+const RECALC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many reports of a batch are forwarded before yielding back to the scheduler.
+const BATCH_YIELD_EVERY: usize = 256;
+
+/// Reports buffered per `/debug/stream` consumer before it counts as lagging.
+const DEBUG_STREAM_CAPACITY: usize = 1024;
+
+/// Furthest into the future a report timestamp may lie; later ones are clamped to
+/// it, so a broken client clock can't pin a report or upset the age arithmetic.
+const MAX_FUTURE_SKEW_SECS: u64 = 24 * 60 * 60;
+
+/// How long `--ack-mode processed` waits for the data actor before answering 503.
+const PROCESSED_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a single Pushgateway push may take before it is abandoned.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many time buckets `--percentile-mode tdigest` splits the window into. More
+/// buckets expire reports more precisely but cost more to merge on every tick.
+const TDIGEST_BUCKETS_PER_WINDOW: u64 = 60;
+
+/// The `/stats/by-region` bucket for reports that don't name a region.
+const UNKNOWN_REGION: &str = "unknown";
+
+static METRICS_RESPONSE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "text/plain; version=0.0.4".parse().unwrap(),
+    );
+    headers
+});
+
 static STATS_RESPONSE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
@@ -32,163 +534,5434 @@ static STATS_RESPONSE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
     headers
 });
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct Report {
+/// `T` is how the timestamp is held: the HTTP handlers take `WireTimestamp` and
+/// `validate` it, everything else works on UNIX seconds.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(bound(deserialize = "T: FromTimestamp"))]
+pub struct Report<T = u64> {
     worker_id: String,
-    pool: String,
+    /// Legacy firmware leaves it out; `enqueue_report` fills in `--default-pool`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pool: Option<String>,
     hashrate: f64,
     temperature: f64,
-    timestamp: u64,
+    /// UNIX seconds. Clients may also send an RFC3339 string, which is converted.
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    timestamp: T,
+    /// Overrides `--expiration-secs` for this report, clamped to `--max-ttl-secs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_secs: Option<u64>,
+    /// Power draw in watts, if the rig measures it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    power: Option<f64>,
+    /// Region or datacenter the worker runs in, for `/stats/by-region`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    /// When the server accepted the report, set by `enqueue_report`. Never on the wire.
+    #[serde(skip)]
+    received_at: Option<Instant>,
+    /// Set when this stands in for a `POST /preaggregated` batch. Never on the wire.
+    #[serde(skip)]
+    batch: Option<Batch>,
+}
+
+/// How many reports from how many workers a pre-aggregated report stands for. Its
+/// `hashrate` and `temperature` are the batch's means.
+#[derive(Debug, Clone, Copy)]
+struct Batch {
+    reports: u64,
+    workers: u64,
+}
+
+impl Report {
+    /// Last second (inclusive) at which this report still counts towards the stats.
+    fn expires_at(&self, config: &DataActorConfig) -> u64 {
+        let ttl = self
+            .ttl_secs
+            .map_or(config.expiration_secs, |ttl| ttl.min(config.max_ttl_secs));
+        self.timestamp.saturating_add(ttl)
+    }
+
+    /// Always set once the report has been through `enqueue_report`.
+    fn pool(&self) -> &str {
+        self.pool.as_deref().unwrap_or_default()
+    }
+
+    fn region(&self) -> &str {
+        self.region.as_deref().unwrap_or(UNKNOWN_REGION)
+    }
+
+    /// How many reports this counts as in averages, sums and rates.
+    fn weight(&self) -> u64 {
+        self.batch.map_or(1, |batch| batch.reports)
+    }
+
+    /// How many workers this counts as while it is its worker id's latest report.
+    fn workers(&self) -> u64 {
+        self.batch.map_or(1, |batch| batch.workers)
+    }
+}
+
+impl Report<WireTimestamp> {
+    /// The report with its timestamp in UNIX seconds, or why the timestamp was
+    /// rejected.
+    fn validate(self) -> Result<Report, String> {
+        Ok(Report {
+            worker_id: self.worker_id,
+            pool: self.pool,
+            hashrate: self.hashrate,
+            temperature: self.temperature,
+            timestamp: self.timestamp.secs()?,
+            ttl_secs: self.ttl_secs,
+            power: self.power,
+            region: self.region,
+            received_at: self.received_at,
+            batch: self.batch,
+        })
+    }
+}
+
+/// A `timestamp` as the client sent it. An RFC3339 string that doesn't parse still
+/// deserializes, so the HTTP handlers can answer it with its own error code rather
+/// than a generic body error.
+#[derive(Debug, Clone)]
+enum WireTimestamp {
+    Secs(u64),
+    Invalid(String),
+}
+
+impl WireTimestamp {
+    fn secs(self) -> Result<u64, String> {
+        match self {
+            Self::Secs(secs) => Ok(secs),
+            Self::Invalid(error) => Err(error),
+        }
+    }
+}
+
+/// What `deserialize_timestamp` can produce.
+trait FromTimestamp: Sized {
+    fn from_secs(secs: u64) -> Self;
+    fn from_rfc3339<E: de::Error>(value: &str) -> Result<Self, E>;
 }
 
-#[derive(Debug, Serialize, Default, Clone)]
+impl FromTimestamp for u64 {
+    fn from_secs(secs: u64) -> Self {
+        secs
+    }
+
+    fn from_rfc3339<E: de::Error>(value: &str) -> Result<Self, E> {
+        parse_rfc3339(value).map_err(E::custom)
+    }
+}
+
+impl FromTimestamp for WireTimestamp {
+    fn from_secs(secs: u64) -> Self {
+        Self::Secs(secs)
+    }
+
+    fn from_rfc3339<E: de::Error>(value: &str) -> Result<Self, E> {
+        Ok(parse_rfc3339(value).map_or_else(Self::Invalid, Self::Secs))
+    }
+}
+
+fn parse_rfc3339(value: &str) -> Result<u64, String> {
+    let parsed = DateTime::parse_from_rfc3339(value)
+        .map_err(|err| format!("invalid RFC3339 timestamp {value:?}: {err}"))?;
+    u64::try_from(parsed.timestamp())
+        .map_err(|_| format!("invalid RFC3339 timestamp {value:?}: before the UNIX epoch"))
+}
+
+fn deserialize_timestamp<'de, D: Deserializer<'de>, T: FromTimestamp>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    struct TimestampVisitor<T>(std::marker::PhantomData<T>);
+
+    impl<T: FromTimestamp> Visitor<'_> for TimestampVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("UNIX seconds or an RFC3339 string")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+            Ok(T::from_secs(value))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+            T::from_rfc3339(value)
+        }
+    }
+
+    deserializer.deserialize_any(TimestampVisitor(std::marker::PhantomData))
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
 pub struct PoolStats {
     workers: usize,
+    // The aliases let a stats cache written with `--output-profile legacy` load.
+    #[serde(alias = "hashrate")]
     avg_hashrate: f64,
+    #[serde(alias = "temp")]
     avg_temp: f64,
+    // Sums over every retained report, not per worker: a worker that reported five
+    // times in the window counts five times. Lets dashboards combine pools exactly.
+    sum_hashrate: f64,
+    sum_temp: f64,
+    // This pool's `pool_hashrate` as a percentage of all live pools' together, so it
+    // matches /summary and /status. Always 0 for regions.
+    hashrate_share_pct: f64,
+    // Each worker's latest hashrate, summed: what the pool is mining at right now.
+    // Unlike `avg_hashrate` times `workers`, idle workers add 0 whatever
+    // `--exclude-zero-hashrate` does to the average. With `--avg-window-secs`, only
+    // workers that reported within it count.
+    pool_hashrate: f64,
+    reports_per_sec: f64,
+    // Workers present now but not on the previous tick, and vice versa.
+    workers_joined: usize,
+    workers_left: usize,
+    // This and the other fields that need every retained report are left out with
+    // `--incremental-stats`, and for pools without reports.
+    // Percentage of workers whose latest report is within `--healthy-within-secs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    healthy_worker_pct: Option<f64>,
+    // Workers whose latest report has a hashrate of exactly 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zero_hashrate_workers: Option<usize>,
+    // Age of the oldest retained report; 0 for future-dated reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oldest_report_age_secs: Option<u64>,
+    // Highest temperature of any retained report, after `--temp-median-window`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_temp: Option<f64>,
+    // `avg_temp` is above the pool's `--temp-alert-threshold`. Always false for regions.
+    temp_alerting: bool,
+    // `pool_hashrate` is below the pool's `--hashrate-floor`. Always
+    // false for regions.
+    hashrate_alerting: bool,
+    // All of the pool's reports have expired and these are its last stats from
+    // before, kept for `--retain-pool-secs`.
+    stale: bool,
+    // `max_report_interval_secs` is above `--interruption-gap-secs`.
+    interrupted: bool,
+    // Only computed with `--percentiles`. A pool with a single report has all three
+    // equal to its temperature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p95_temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p99_temp: Option<f64>,
+    // Only present once some report in the window carries `power`. Efficiency is
+    // hashrate per watt over just those reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_power: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_efficiency: Option<f64>,
+    // Gaps between consecutive reports of the pool; 0 with fewer than two reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_report_interval_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_report_interval_secs: Option<u64>,
+    // From the server accepting each report received since the previous tick to this
+    // tick's recalculation, i.e. the lag added by queueing and processing, as opposed
+    // to `oldest_report_age_secs`, which includes the network. 0 without new reports
+    // and for regions.
+    avg_processing_delay_secs: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct TotalStats {
+    reports_per_sec: f64,
+    // Distinct worker ids across all pools: a worker reporting to two pools counts
+    // once. The number of physical rigs, e.g. for billing.
+    workers: usize,
+    // Sum of the pools' `workers`, so that same worker counts twice. Matches adding
+    // up the per-pool numbers, e.g. for per-pool monitoring. Stale pools are left out
+    // of both.
+    pool_workers: usize,
 }
 
-#[derive(Debug, Serialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
 pub struct AllStats {
     // using BTreeMap instead of HashMap to keep the stats sorted by pool name
     pools: BTreeMap<String, PoolStats>,
+    total: TotalStats,
+    // UNIX time of the tick that produced this snapshot, so clients can spot a stale
+    // one. 0 until the data actor publishes for the first time.
+    generated_at: u64,
+    // The same stats rolled up across pools by report region. Served by
+    // `/stats/by-region` only, so it is left out of the `/stats` JSON.
+    #[serde(skip)]
+    regions: BTreeMap<String, PoolStats>,
+    // Only set on the `/stats` JSON when `--max-pools-in-response` left pools out.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_pools: Option<usize>,
 }
 
-#[derive(Clone)]
-struct AppState {
-    report_tx: mpsc::Sender<Report>,
-    stats_rx: watch::Receiver<String>,
-}
+impl PoolStats {
+    /// Feeds `AllStats::content_hash`. Destructured so that a new field can't be
+    /// left out of the hash by accident.
+    fn hash_content(&self, hasher: &mut DefaultHasher) {
+        let Self {
+            workers,
+            avg_hashrate,
+            avg_temp,
+            sum_hashrate,
+            sum_temp,
+            hashrate_share_pct,
+            pool_hashrate,
+            reports_per_sec,
+            workers_joined,
+            workers_left,
+            healthy_worker_pct,
+            zero_hashrate_workers,
+            oldest_report_age_secs,
+            max_temp,
+            temp_alerting,
+            hashrate_alerting,
+            stale,
+            interrupted,
+            median_temp,
+            p95_temp,
+            p99_temp,
+            avg_power,
+            avg_efficiency,
+            avg_report_interval_secs,
+            max_report_interval_secs,
+            avg_processing_delay_secs,
+        } = self;
+        for value in [
+            avg_hashrate,
+            avg_temp,
+            sum_hashrate,
+            sum_temp,
+            hashrate_share_pct,
+            pool_hashrate,
+        ] {
+            value.to_bits().hash(hasher);
+        }
+        for value in [
+            healthy_worker_pct,
+            avg_report_interval_secs,
+            max_temp,
+            median_temp,
+            p95_temp,
+            p99_temp,
+            avg_power,
+            avg_efficiency,
+        ] {
+            value.map(f64::to_bits).hash(hasher);
+        }
+        (workers, zero_hashrate_workers, max_report_interval_secs).hash(hasher);
+        (temp_alerting, hashrate_alerting, stale, interrupted).hash(hasher);
+        // Per tick or growing with the clock: see `AllStats::content_hash`.
+        for value in [reports_per_sec, avg_processing_delay_secs] {
+            (*value != 0.0).hash(hasher);
+        }
+        (
+            *workers_joined != 0,
+            *workers_left != 0,
+            oldest_report_age_secs.map(|age| age != 0),
+        )
+            .hash(hasher);
+    }
 
-async fn post_report(
-    State(state): State<AppState>,
-    Json(report): Json<Report>,
-) -> impl IntoResponse {
-    if state.report_tx.send(report).await.is_err() {
-        error!("Report channel is closed. This is a critical internal error.");
-        return StatusCode::INTERNAL_SERVER_ERROR;
+    /// Blends the fractional fields with `previous`, keeping `weight` of the new value.
+    /// Optional fields missing from either side take the new value as is.
+    fn smooth(&mut self, previous: &PoolStats, weight: f64, temp_precision: u32) {
+        let blend = |new: f64, old: f64| weight * new + (1.0 - weight) * old;
+        let blend_opt = |new: Option<f64>, old: Option<f64>| match (new, old) {
+            (Some(new), Some(old)) => Some(blend(new, old)),
+            (new, _) => new,
+        };
+
+        self.avg_hashrate = blend(self.avg_hashrate, previous.avg_hashrate);
+        self.avg_temp = round_to(blend(self.avg_temp, previous.avg_temp), temp_precision);
+        self.sum_hashrate = blend(self.sum_hashrate, previous.sum_hashrate);
+        self.sum_temp = blend(self.sum_temp, previous.sum_temp);
+        self.hashrate_share_pct = blend(self.hashrate_share_pct, previous.hashrate_share_pct);
+        self.pool_hashrate = blend(self.pool_hashrate, previous.pool_hashrate);
+        self.reports_per_sec = blend(self.reports_per_sec, previous.reports_per_sec);
+        self.healthy_worker_pct = blend_opt(self.healthy_worker_pct, previous.healthy_worker_pct);
+        self.max_temp = blend_opt(self.max_temp, previous.max_temp);
+        self.median_temp = blend_opt(self.median_temp, previous.median_temp);
+        self.p95_temp = blend_opt(self.p95_temp, previous.p95_temp);
+        self.p99_temp = blend_opt(self.p99_temp, previous.p99_temp);
+        self.avg_power = blend_opt(self.avg_power, previous.avg_power);
+        self.avg_efficiency = blend_opt(self.avg_efficiency, previous.avg_efficiency);
+        self.avg_report_interval_secs = blend_opt(
+            self.avg_report_interval_secs,
+            previous.avg_report_interval_secs,
+        );
+        self.avg_processing_delay_secs = blend(
+            self.avg_processing_delay_secs,
+            previous.avg_processing_delay_secs,
+        );
     }
-    StatusCode::OK
 }
 
-async fn get_stats(State(state): State<AppState>) -> impl IntoResponse {
-    (
-        STATS_RESPONSE_HEADERS.clone(),
-        state.stats_rx.borrow().clone(),
-    )
-}
+impl AllStats {
+    /// A copy with only the pools `keep` accepts; the totals still cover every pool.
+    /// Filtering out every pool leaves a valid snapshot with empty `pools`, not an
+    /// error, so filtering handlers can serve it as is.
+    fn filter_pools(&self, keep: impl Fn(&str, &PoolStats) -> bool) -> AllStats {
+        AllStats {
+            pools: self
+                .pools
+                .iter()
+                .filter(|(pool, stats)| keep(pool, stats))
+                .map(|(pool, stats)| (pool.clone(), stats.clone()))
+                .collect(),
+            total: self.total.clone(),
+            generated_at: self.generated_at,
+            ..AllStats::default()
+        }
+    }
 
-async fn data_actor(
-    mut report_rx: mpsc::Receiver<Report>,
-    stats_tx: watch::Sender<String>,
-    expiration_secs: u64,
-) {
-    let mut pools_data: HashMap<String, VecDeque<Report>> = HashMap::new();
-    let mut calculation_interval = tokio::time::interval(Duration::from_secs(1));
+    /// With more than `max_pools` pools, a copy with only the `max_pools` of them
+    /// with the highest `sum_hashrate`, marked `truncated`. `None` if all of them fit.
+    fn truncated(&self, max_pools: Option<NonZeroUsize>) -> Option<AllStats> {
+        let max_pools = max_pools?.get();
+        if self.pools.len() <= max_pools {
+            return None;
+        }
+        let mut by_hashrate: Vec<_> = self.pools.iter().collect();
+        by_hashrate.sort_unstable_by(|(_, a), (_, b)| b.sum_hashrate.total_cmp(&a.sum_hashrate));
+        by_hashrate.truncate(max_pools);
+        Some(AllStats {
+            pools: by_hashrate
+                .into_iter()
+                .map(|(pool, stats)| (pool.clone(), stats.clone()))
+                .collect(),
+            total: self.total.clone(),
+            generated_at: self.generated_at,
+            regions: BTreeMap::new(),
+            truncated: true,
+            total_pools: Some(self.pools.len()),
+        })
+    }
 
-    loop {
-        tokio::select! {
-            // Branch 1: A new report is received from a web handler.
-            Some(report) = report_rx.recv() => {
-                pools_data.entry(report.pool.clone()).or_default().push_back(report);
+    /// Hash of everything but `generated_at`, to tell whether two snapshots differ
+    /// without comparing or serializing them. The report age and the per-tick rates,
+    /// delays and worker deltas only count as zero or not: they move on every tick,
+    /// but a snapshot still has to go out when one of them drops to 0, or `/stats`
+    /// would keep showing the last non-zero rate after ingest stops.
+    fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for stats in [&self.pools, &self.regions] {
+            stats.len().hash(&mut hasher);
+            for (name, stats) in stats {
+                name.hash(&mut hasher);
+                stats.hash_content(&mut hasher);
             }
+        }
+        let TotalStats {
+            reports_per_sec,
+            workers,
+            pool_workers,
+        } = &self.total;
+        (*reports_per_sec != 0.0).hash(&mut hasher);
+        workers.hash(&mut hasher);
+        pool_workers.hash(&mut hasher);
+        hasher.finish()
+    }
+}
 
-            // Branch 2: The 1-second timer ticks, triggering a stats recalculation.
-            _ = calculation_interval.tick() => {
-                let now_ts = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs();
+/// `PoolStats` under the field names of `--output-profile legacy`.
+#[derive(Debug, Serialize)]
+struct LegacyPoolStats {
+    workers: usize,
+    #[serde(rename = "hashrate")]
+    avg_hashrate: f64,
+    #[serde(rename = "temp")]
+    avg_temp: f64,
+    sum_hashrate: f64,
+    sum_temp: f64,
+    hashrate_share_pct: f64,
+    pool_hashrate: f64,
+    reports_per_sec: f64,
+    workers_joined: usize,
+    workers_left: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    healthy_worker_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zero_hashrate_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oldest_report_age_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_temp: Option<f64>,
+    temp_alerting: bool,
+    hashrate_alerting: bool,
+    stale: bool,
+    interrupted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    median_temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p95_temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p99_temp: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_power: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_efficiency: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_report_interval_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_report_interval_secs: Option<u64>,
+    avg_processing_delay_secs: f64,
+}
 
-                let expiration_ts = now_ts.saturating_sub(expiration_secs);
+impl From<&PoolStats> for LegacyPoolStats {
+    fn from(stats: &PoolStats) -> Self {
+        Self {
+            workers: stats.workers,
+            avg_hashrate: stats.avg_hashrate,
+            avg_temp: stats.avg_temp,
+            sum_hashrate: stats.sum_hashrate,
+            sum_temp: stats.sum_temp,
+            hashrate_share_pct: stats.hashrate_share_pct,
+            pool_hashrate: stats.pool_hashrate,
+            reports_per_sec: stats.reports_per_sec,
+            workers_joined: stats.workers_joined,
+            workers_left: stats.workers_left,
+            healthy_worker_pct: stats.healthy_worker_pct,
+            zero_hashrate_workers: stats.zero_hashrate_workers,
+            oldest_report_age_secs: stats.oldest_report_age_secs,
+            max_temp: stats.max_temp,
+            temp_alerting: stats.temp_alerting,
+            hashrate_alerting: stats.hashrate_alerting,
+            stale: stats.stale,
+            interrupted: stats.interrupted,
+            median_temp: stats.median_temp,
+            p95_temp: stats.p95_temp,
+            p99_temp: stats.p99_temp,
+            avg_power: stats.avg_power,
+            avg_efficiency: stats.avg_efficiency,
+            avg_report_interval_secs: stats.avg_report_interval_secs,
+            max_report_interval_secs: stats.max_report_interval_secs,
+            avg_processing_delay_secs: stats.avg_processing_delay_secs,
+        }
+    }
+}
 
-                let pools = pools_data.iter_mut()
-                    .map(|(pool_name, deque)| {
-                        // Step 1: Prune old reports from the front of the deque.
-                        while let Some(report) = deque.front() {
-                            if report.timestamp < expiration_ts {
-                                deque.pop_front();
-                            } else {
-                                break;
-                            }
-                        }
+/// `AllStats` with its pools as `LegacyPoolStats`.
+#[derive(Debug, Serialize)]
+struct LegacyAllStats<'a> {
+    pools: BTreeMap<&'a str, LegacyPoolStats>,
+    total: &'a TotalStats,
+    generated_at: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_pools: Option<usize>,
+}
 
-                        // Step 2: Calculate all required values in a single pass using fold.
-                        let (total_hashrate, total_temp, unique_workers) = deque.iter().fold(
-                            // The initial state of our accumulator: (hash, temp, worker_set)
-                            (0.0, 0.0, HashSet::new()),
-                            // The closure to update the accumulator for each report
-                            |(h_acc, t_acc, mut workers_set), report| {
-                                workers_set.insert(&report.worker_id);
-                                (h_acc + report.hashrate, t_acc + report.temperature, workers_set)
-                            },
-                        );
+impl<'a> From<&'a AllStats> for LegacyAllStats<'a> {
+    fn from(stats: &'a AllStats) -> Self {
+        Self {
+            pools: stats
+                .pools
+                .iter()
+                .map(|(pool, pool_stats)| (pool.as_str(), pool_stats.into()))
+                .collect(),
+            total: &stats.total,
+            generated_at: stats.generated_at,
+            truncated: stats.truncated,
+            total_pools: stats.total_pools,
+        }
+    }
+}
 
-                        // Step 3: Create the final stats struct for this pool.
-                        let pool_stats = if !deque.is_empty() {
-                            PoolStats {
-                                workers: unique_workers.len(),
-                                avg_hashrate: total_hashrate / deque.len() as f64,
-                                avg_temp: total_temp / deque.len() as f64,
-                            }
-                        } else {
-                            // If there are no reports, return a default state with 0 workers and 0.0 averages.
-                            PoolStats::default()
-                        };
+/// A published stats snapshot, kept both structured and pre-serialized so that
+/// `/stats` can hand out the JSON as-is while other endpoints read the fields.
+#[derive(Debug)]
+struct PublishedStats {
+    stats: AllStats,
+    json: String,
+}
 
-                        (pool_name.clone(), pool_stats)
-                    })
-                    .collect::<BTreeMap<_, _>>();
+impl PublishedStats {
+    /// `stats` stays complete, only the JSON is cut down to `max_pools`.
+    fn new(
+        stats: AllStats,
+        profile: OutputProfile,
+        max_pools: Option<NonZeroUsize>,
+    ) -> serde_json::Result<Self> {
+        let truncated = stats.truncated(max_pools);
+        let json = Self::to_json(truncated.as_ref().unwrap_or(&stats), profile)?;
+        Ok(Self { stats, json })
+    }
 
-                // Step 4: Assemble the final stats object and publish it.
-                let current_stats = AllStats { pools };
+    /// `stats` as JSON under the field names of `profile`.
+    fn to_json(stats: &AllStats, profile: OutputProfile) -> serde_json::Result<String> {
+        match profile {
+            OutputProfile::Default => serde_json::to_string(stats),
+            OutputProfile::Legacy => serde_json::to_string(&LegacyAllStats::from(stats)),
+        }
+    }
 
-                if let Ok(json) = serde_json::to_string(&current_stats) {
-                    info!(stats = %json, "Publishing new stats");
-                    // Send the new stats to all subscribed `get_stats` handlers.
-                    stats_tx.send(json).ok();
-                }
+    /// The snapshot `/stats` serves until the data actor publishes for the first time.
+    fn initial() -> Self {
+        // Without pools, both output profiles render the same.
+        Self::new(AllStats::default(), OutputProfile::Default, None).unwrap_or_else(|err| {
+            error!(error = %err, "Failed to serialize the initial stats, serving an empty object");
+            Self {
+                stats: AllStats::default(),
+                json: "{}".to_string(),
             }
+        })
+    }
+}
 
-            // Branch 3: The report channel has closed, so the actor should shut down.
-            else => {
-                info!("Report channel closed. Data actor shutting down.");
-                break;
-            }
+/// The `received_at` of a set of reports as a count and a sum, so their mean delay
+/// doesn't depend on where the reports sit in the deque. Reports without one, e.g.
+/// replayed, don't count.
+#[derive(Debug)]
+struct ReceivedTimes {
+    count: usize,
+    // Seconds from `base` to each `received_at`, negative for earlier ones.
+    total_secs: f64,
+    base: Instant,
+}
+
+impl Default for ReceivedTimes {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total_secs: 0.0,
+            base: Instant::now(),
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+impl ReceivedTimes {
+    fn offset_secs(&self, instant: Instant) -> f64 {
+        match instant.checked_duration_since(self.base) {
+            Some(after) => after.as_secs_f64(),
+            None => -self.base.duration_since(instant).as_secs_f64(),
+        }
+    }
 
-    let cli = Cli::parse();
-    info!(config = ?cli, "Service starting with configuration");
+    fn add(&mut self, report: &Report) {
+        if let Some(received_at) = report.received_at {
+            self.count += 1;
+            self.total_secs += self.offset_secs(received_at);
+        }
+    }
 
-    let (report_tx, report_rx) = mpsc::channel::<Report>(1024);
-    let (stats_tx, stats_rx) = watch::channel(serde_json::to_string(&AllStats::default()).unwrap());
+    fn remove(&mut self, report: &Report) {
+        if let Some(received_at) = report.received_at
+            && self.count > 0
+        {
+            self.count -= 1;
+            self.total_secs -= self.offset_secs(received_at);
+        }
+    }
 
-    info!("Spawning data actor...");
-    tokio::spawn(data_actor(report_rx, stats_tx, cli.expiration_secs));
+    /// Mean time from each `received_at` until `recalculated_at`.
+    fn avg_delay_secs(&self, recalculated_at: Instant) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let avg = self.offset_secs(recalculated_at) - self.total_secs / self.count as f64;
+        avg.max(0.0)
+    }
+}
 
-    let app_state = AppState {
-        report_tx,
-        stats_rx,
-    };
+/// A report on its way to the data actor. With `--ack-mode processed` the sender
+/// waits on `processed_tx` until the report has been added to its pool.
+#[derive(Debug)]
+struct QueuedReport {
+    report: Report,
+    processed_tx: Option<oneshot::Sender<()>>,
+}
 
-    let app = Router::new()
-        .route("/report", post(post_report))
-        .route("/stats", get(get_stats))
-        .with_state(app_state);
+/// Everything the data actor keeps for a single pool.
+#[derive(Debug)]
+struct PoolData {
+    reports: VecDeque<Report>,
+    // Reports received since the previous tick, reset on every recalculation.
+    reports_since_tick: u64,
+    // When the reports received since the previous tick were accepted, for
+    // `avg_processing_delay_secs`.
+    received_since_tick: ReceivedTimes,
+    // Reports received over the pool's lifetime, for `--min-reports-to-appear`.
+    reports_received: u64,
+    // Newest report timestamp ever seen, for `--expire-relative-to newest`.
+    newest_timestamp: u64,
+    // Disabled pools keep ingesting reports but are left out of `AllStats`.
+    enabled: bool,
+    // Unique workers seen on the previous tick, used to compute churn.
+    previous_workers: HashSet<String>,
+    // Only maintained with `--incremental-stats`.
+    totals: PoolTotals,
+    // When the last report arrived and the stats it last had reports for, for
+    // `--retain-pool-secs`.
+    last_report_at: Instant,
+    last_stats: Option<PoolStats>,
+    // Only maintained with `--percentile-mode tdigest`.
+    temp_digests: TempDigests,
+    alerts: PoolAlerts,
+}
 
-    let addr = "127.0.0.1:3000";
-    info!("Server listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+impl Default for PoolData {
+    fn default() -> Self {
+        Self {
+            reports: VecDeque::new(),
+            reports_since_tick: 0,
+            received_since_tick: ReceivedTimes::default(),
+            reports_received: 0,
+            newest_timestamp: 0,
+            enabled: true,
+            previous_workers: HashSet::new(),
+            totals: PoolTotals::default(),
+            last_report_at: Instant::now(),
+            last_stats: None,
+            temp_digests: TempDigests::default(),
+            alerts: PoolAlerts::default(),
+        }
+    }
+}
 
-    Ok(())
+impl PoolData {
+    /// Takes out the reports stamped at or after `window_start`, the start of the
+    /// tumbling window that has just begun. They arrived between the boundary and
+    /// the tick that noticed it and belong to the new window, so they don't count
+    /// towards the closing one's stats, rates or percentiles.
+    fn split_off_next_window(
+        &mut self,
+        window_start: u64,
+        config: &DataActorConfig,
+    ) -> Vec<Report> {
+        let (next, current) = std::mem::take(&mut self.reports)
+            .into_iter()
+            .partition::<Vec<_>, _>(|report| report.timestamp >= window_start);
+        self.reports = current.into();
+        for report in &next {
+            self.reports_since_tick = self.reports_since_tick.saturating_sub(report.weight());
+            self.received_since_tick.remove(report);
+            if config.incremental_stats {
+                self.totals.remove(report);
+            }
+        }
+        self.temp_digests
+            .pending
+            .retain(|&(timestamp, _)| timestamp < window_start);
+        next
+    }
+
+    /// Empties the pool for the next tumbling window, which starts out with the
+    /// reports `split_off_next_window` took out.
+    fn start_next_window(&mut self, carried: Vec<Report>, config: &DataActorConfig) {
+        self.reports.clear();
+        self.totals = PoolTotals::default();
+        self.temp_digests = TempDigests::default();
+        for report in carried {
+            self.reports_since_tick += report.weight();
+            self.received_since_tick.add(&report);
+            if config.incremental_stats {
+                self.totals.add(&report);
+            }
+            if config.percentiles && config.percentile_mode == PercentileMode::Tdigest {
+                self.temp_digests.add(&report);
+            }
+            self.reports.push_back(report);
+        }
+    }
+}
+
+/// Start of the `--window-mode tumbling` window `timestamp` falls into.
+fn tumbling_window_start(timestamp: u64, config: &DataActorConfig) -> u64 {
+    timestamp - timestamp % config.expiration_secs.max(1)
+}
+
+/// Whether each of a pool's alerts fired on the previous tick, so that only their
+/// transitions get logged.
+#[derive(Debug, Default)]
+struct PoolAlerts {
+    temp: bool,
+    hashrate: bool,
+}
+
+impl PoolAlerts {
+    /// Resolves every firing alert, logging it, for when the pool's data is dropped.
+    fn clear(&mut self, pool: &str) {
+        for (alert, firing) in [("temp", &mut self.temp), ("hashrate", &mut self.hashrate)] {
+            if *firing {
+                info!(pool, alert, "Alert resolved, stats were reset");
+                *firing = false;
+            }
+        }
+    }
+
+    /// Checks `stats` against the pool's thresholds and sets its alert flags.
+    fn evaluate(&mut self, pool: &str, stats: &mut PoolStats, config: &DataActorConfig) {
+        stats.temp_alerting = update_alert(
+            pool,
+            "temp",
+            &mut self.temp,
+            stats.avg_temp,
+            config.temp_alert_threshold(pool),
+            |temp, threshold| temp > threshold,
+        );
+        stats.hashrate_alerting = update_alert(
+            pool,
+            "hashrate",
+            &mut self.hashrate,
+            stats.pool_hashrate,
+            config.hashrate_floor(pool),
+            |hashrate, floor| hashrate < floor,
+        );
+    }
+}
+
+/// Whether `value` breaches `limit` now, logging if that changed since the last call.
+fn update_alert(
+    pool: &str,
+    alert: &str,
+    firing: &mut bool,
+    value: f64,
+    limit: Option<f64>,
+    breaches: impl Fn(f64, f64) -> bool,
+) -> bool {
+    let Some(limit) = limit else {
+        return false;
+    };
+    let now_firing = breaches(value, limit);
+    if now_firing != *firing {
+        if now_firing {
+            warn!(pool, alert, value, limit, "Alert firing");
+        } else {
+            info!(pool, alert, value, limit, "Alert resolved");
+        }
+        *firing = now_firing;
+    }
+    now_firing
+}
+
+/// A pool's temperatures for `--percentile-mode tdigest`, as one t-digest per
+/// time bucket of their report timestamps. Temperatures are collected as reports
+/// arrive and folded into their bucket on the next tick, and a bucket is dropped
+/// as a whole once its newest possible report has expired. Memory is bounded by
+/// the bucket count rather than the report count.
+#[derive(Debug, Default)]
+struct TempDigests {
+    buckets: BTreeMap<u64, TDigest>,
+    // (timestamp, temperature) received since the last tick.
+    pending: Vec<(u64, f64)>,
+}
+
+impl TempDigests {
+    fn add(&mut self, report: &Report) {
+        if report.temperature.is_finite() {
+            self.pending.push((report.timestamp, report.temperature));
+        }
+    }
+
+    /// Folds the pending temperatures into their buckets and, given the reference
+    /// time expiry is measured against, drops the buckets that have fully expired.
+    fn update(&mut self, config: &DataActorConfig, reference_ts: Option<u64>) {
+        let bucket_secs = (config.expiration_secs / TDIGEST_BUCKETS_PER_WINDOW).max(1);
+
+        let mut by_bucket: HashMap<u64, Vec<f64>> = HashMap::new();
+        for (timestamp, temp) in self.pending.drain(..) {
+            by_bucket
+                .entry(timestamp / bucket_secs)
+                .or_default()
+                .push(temp);
+        }
+        for (bucket, temps) in by_bucket {
+            let digest = self.buckets.entry(bucket).or_default();
+            *digest = digest.merge_unsorted(temps);
+        }
+
+        if let Some(reference_ts) = reference_ts {
+            self.buckets.retain(|bucket, _| {
+                let newest_timestamp = (bucket + 1).saturating_mul(bucket_secs) - 1;
+                newest_timestamp.saturating_add(config.expiration_secs) >= reference_ts
+            });
+        }
+    }
+
+    /// Approximate p50, p95 and p99 across all buckets.
+    fn quantiles(&self) -> Option<(f64, f64, f64)> {
+        let digest = TDigest::merge_digests(self.buckets.values().cloned().collect());
+        if digest.is_empty() {
+            return None;
+        }
+        Some((
+            digest.estimate_quantile(0.5),
+            digest.estimate_quantile(0.95),
+            digest.estimate_quantile(0.99),
+        ))
+    }
+}
+
+/// Sums over a pool's retained reports, adjusted as reports are added and pruned
+/// so `--incremental-stats` doesn't have to fold the whole deque every tick.
+#[derive(Debug, Default)]
+struct RunningTotals {
+    reports: usize,
+    hashrate: f64,
+    temp: f64,
+    powered_reports: usize,
+    powered_hashrate: f64,
+    power: f64,
+    // Retained reports per worker id, and the workers and hashrate of its last added
+    // report. A worker is gone once its count drops to zero.
+    workers: HashMap<String, (usize, u64, f64)>,
+}
+
+impl RunningTotals {
+    fn add(&mut self, report: &Report) {
+        let weight = report.weight();
+        self.reports += weight as usize;
+        self.hashrate += report.hashrate * weight as f64;
+        self.temp += report.temperature * weight as f64;
+        if let Some(power) = report.power {
+            self.powered_reports += 1;
+            self.powered_hashrate += report.hashrate;
+            self.power += power;
+        }
+        match self.workers.get_mut(&report.worker_id) {
+            Some((count, workers, hashrate)) => {
+                *count += 1;
+                *workers = report.workers();
+                *hashrate = report.hashrate;
+            }
+            None => {
+                self.workers.insert(
+                    report.worker_id.clone(),
+                    (1, report.workers(), report.hashrate),
+                );
+            }
+        }
+    }
+
+    fn remove(&mut self, report: &Report) {
+        let weight = report.weight();
+        self.reports -= weight as usize;
+        self.hashrate -= report.hashrate * weight as f64;
+        self.temp -= report.temperature * weight as f64;
+        if let Some(power) = report.power {
+            self.powered_reports -= 1;
+            self.powered_hashrate -= report.hashrate;
+            self.power -= power;
+        }
+        if let Some((count, _, _)) = self.workers.get_mut(&report.worker_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.workers.remove(&report.worker_id);
+            }
+        }
+        // Leftover rounding error would otherwise show up as a tiny non-zero average.
+        if self.reports == 0 {
+            *self = Self::default();
+        }
+    }
+
+    /// Adds `other`'s reports, as if they had been added here too.
+    fn merge(&mut self, other: &Self) {
+        self.reports += other.reports;
+        self.hashrate += other.hashrate;
+        self.temp += other.temp;
+        self.powered_reports += other.powered_reports;
+        self.powered_hashrate += other.powered_hashrate;
+        self.power += other.power;
+        for (worker, &(count, workers, hashrate)) in &other.workers {
+            let entry = self.workers.entry(worker.clone()).or_default();
+            entry.0 += count;
+            entry.1 = workers;
+            entry.2 = hashrate;
+        }
+    }
+}
+
+/// A pool's `RunningTotals`, both overall and split by region, so the per-region
+/// stats don't need a pass over the reports either.
+#[derive(Debug, Default)]
+struct PoolTotals {
+    pool: RunningTotals,
+    regions: HashMap<String, RunningTotals>,
+}
+
+impl PoolTotals {
+    fn from_reports<'a>(reports: impl Iterator<Item = &'a Report>) -> Self {
+        let mut totals = Self::default();
+        for report in reports {
+            totals.add(report);
+        }
+        totals
+    }
+
+    fn add(&mut self, report: &Report) {
+        self.pool.add(report);
+        match self.regions.get_mut(report.region()) {
+            Some(region) => region.add(report),
+            None => {
+                let mut region = RunningTotals::default();
+                region.add(report);
+                self.regions.insert(report.region().to_string(), region);
+            }
+        }
+    }
+
+    fn remove(&mut self, report: &Report) {
+        self.pool.remove(report);
+        if let Some(region) = self.regions.get_mut(report.region()) {
+            region.remove(report);
+            if region.reports == 0 {
+                self.regions.remove(report.region());
+            }
+        }
+    }
+}
+
+/// Tunables the data actor needs from the CLI.
+#[derive(Debug, Clone)]
+struct DataActorConfig {
+    expiration_secs: u64,
+    max_ttl_secs: u64,
+    min_reports_to_appear: u64,
+    percentiles: bool,
+    percentile_mode: PercentileMode,
+    expire_relative_to: ExpireRelativeTo,
+    window_mode: WindowMode,
+    max_reports_per_iteration: NonZeroUsize,
+    // Prune on every this many ticks, from `--prune-interval-secs`.
+    prune_interval_ticks: u64,
+    per_second_dedup: bool,
+    exclude_zero_hashrate: bool,
+    avg_window_secs: Option<u64>,
+    temp_median_window: Option<usize>,
+    max_reports_per_worker: Option<NonZeroUsize>,
+    incremental_stats: bool,
+    incremental_resync_ticks: u64,
+    healthy_within_secs: u64,
+    interruption_gap_secs: Option<u64>,
+    temp_precision: u32,
+    temp_alert_threshold: Option<f64>,
+    // Overrides of `temp_alert_threshold`, keyed by normalized pool name.
+    pool_temp_alert_thresholds: HashMap<String, f64>,
+    hashrate_floor: Option<f64>,
+    // Overrides of `hashrate_floor`, keyed by normalized pool name.
+    pool_hashrate_floors: HashMap<String, f64>,
+    show_empty_pools: bool,
+    retain_pool: Option<Duration>,
+    publish_on_change: bool,
+    publish_smoothing: Option<f64>,
+    max_pools_in_response: Option<NonZeroUsize>,
+    output_profile: OutputProfile,
+    // Don't publish until the first report arrives, so cached stats stay visible.
+    hold_initial_snapshot: bool,
+}
+
+impl DataActorConfig {
+    fn temp_alert_threshold(&self, pool: &str) -> Option<f64> {
+        self.pool_temp_alert_thresholds
+            .get(pool)
+            .copied()
+            .or(self.temp_alert_threshold)
+    }
+
+    fn hashrate_floor(&self, pool: &str) -> Option<f64> {
+        self.pool_hashrate_floors
+            .get(pool)
+            .copied()
+            .or(self.hashrate_floor)
+    }
+}
+
+/// Requests other than reports that the data actor handles on its own task.
+#[derive(Debug)]
+enum DataActorCommand {
+    /// Replies with `false` if the pool is unknown.
+    SetPoolEnabled {
+        pool: String,
+        enabled: bool,
+        reply_tx: oneshot::Sender<bool>,
+    },
+    /// Bins the pool's retained hashrates into `edges`; `None` if the pool is unknown.
+    HashrateHistogram {
+        pool: String,
+        edges: Vec<f64>,
+        reply_tx: oneshot::Sender<Option<Histogram>>,
+    },
+    /// Collects a worker's latest report in every enabled pool.
+    WorkerStats {
+        worker_id: String,
+        reply_tx: oneshot::Sender<BTreeMap<String, WorkerPoolStats>>,
+    },
+    /// Forgets every pool and report, including those still queued. Disabled pools
+    /// stay disabled.
+    Reset { reply_tx: oneshot::Sender<()> },
+}
+
+/// A worker's latest report within one pool.
+#[derive(Debug, Serialize, Clone)]
+struct WorkerPoolStats {
+    hashrate: f64,
+    temperature: f64,
+    timestamp: u64,
+}
+
+/// Response of `GET /worker/{worker_id}`.
+#[derive(Debug, Serialize)]
+struct WorkerStats {
+    worker_id: String,
+    pools: BTreeMap<String, WorkerPoolStats>,
+    // Sum of the latest hashrate in each pool.
+    total_hashrate: f64,
+    // Mean of the latest temperature in each pool.
+    avg_temp: f64,
+}
+
+/// Counts of values per `[edges[i], edges[i + 1])` bucket, plus the values outside.
+#[derive(Debug, Serialize)]
+struct Histogram {
+    edges: Vec<f64>,
+    counts: Vec<u64>,
+    underflow: u64,
+    overflow: u64,
+}
+
+impl Histogram {
+    /// `edges` must be finite and strictly increasing, see `parse_bucket_edges`.
+    fn new(edges: Vec<f64>, values: impl Iterator<Item = f64>) -> Self {
+        let mut counts = vec![0; edges.len().saturating_sub(1)];
+        let (mut underflow, mut overflow) = (0, 0);
+
+        for value in values {
+            // Index of the first edge greater than the value.
+            let upper = edges.partition_point(|edge| *edge <= value);
+            if upper == 0 {
+                underflow += 1;
+            } else if upper == edges.len() {
+                overflow += 1;
+            } else {
+                counts[upper - 1] += 1;
+            }
+        }
+
+        Self {
+            edges,
+            counts,
+            underflow,
+            overflow,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    report_tx: mpsc::Sender<QueuedReport>,
+    ack_mode: AckMode,
+    report_response: ReportResponse,
+    command_tx: mpsc::Sender<DataActorCommand>,
+    stats_rx: watch::Receiver<Arc<PublishedStats>>,
+    // Tees every ingested report to `/debug/stream` consumers.
+    debug_tx: broadcast::Sender<Report>,
+    max_batch_size: usize,
+    default_pool: Option<String>,
+    pool_case: PoolCase,
+    trim_worker_id: bool,
+    lowercase_worker_id: bool,
+    max_pool_name_len: usize,
+    max_worker_id_len: usize,
+    expired_reports: ExpiredReports,
+    // Reports rejected or dropped for having expired before they arrived.
+    expired_on_arrival: Arc<AtomicU64>,
+    // Reports handed to the data actor since startup, over every ingest path.
+    reports_ingested: Arc<AtomicU64>,
+    // Cleared by `supervise_data_actor` if the data actor dies, failing `/readyz`.
+    data_actor_alive: Arc<AtomicBool>,
+    // The data actor's copy, to tell whether a report has expired on arrival.
+    data_actor_config: Arc<DataActorConfig>,
+    // `GET /config`, rendered once at startup.
+    config_json: Arc<str>,
+    // `POST /admin/shutdown` wakes `shutdown_signal` through this.
+    shutdown_requested: Arc<Notify>,
+}
+
+/// Correlates a report's client and server logs without being part of the report.
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-sent `X-Request-Id` that is kept; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Runs a report handler in a span carrying the request's `X-Request-Id`, or a new
+/// UUID if it has none (or an unusable one), and echoes the id on the response.
+async fn with_request_id(headers: &HeaderMap, handler: impl Future<Output = Response>) -> Response {
+    let request_id = headers
+        .get(&REQUEST_ID)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .and_then(|id| id.to_str().ok())
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    let mut response = handler
+        .instrument(info_span!("report", request_id = %request_id))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    response
+}
+
+/// A report sent as `application/x-www-form-urlencoded`, for firmware that can't
+/// send JSON. Only the basic fields, and `timestamp` only as UNIX seconds.
+#[derive(Debug, Deserialize)]
+struct FormReport {
+    worker_id: String,
+    pool: Option<String>,
+    hashrate: f64,
+    temperature: f64,
+    timestamp: u64,
+}
+
+impl From<FormReport> for Report {
+    fn from(report: FormReport) -> Self {
+        Self {
+            worker_id: report.worker_id,
+            // HTML forms send an empty field as `pool=`, which means no pool.
+            pool: report.pool.filter(|pool| !pool.is_empty()),
+            hashrate: report.hashrate,
+            temperature: report.temperature,
+            timestamp: report.timestamp,
+            ttl_secs: None,
+            power: None,
+            region: None,
+            received_at: None,
+            batch: None,
+        }
+    }
+}
+
+fn is_form(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| {
+            mime.trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+}
+
+async fn accept_form_report(state: &AppState, request: Request) -> Response {
+    match parse_form_report(request).await {
+        Ok(report) => accept_report(state, report).await,
+        Err(response) => response,
+    }
+}
+
+async fn parse_form_report(request: Request) -> Result<Report, Response> {
+    Form::<FormReport>::from_request(request, &())
+        .await
+        .map(|Form(report)| report.into())
+        .map_err(form_rejection_response)
+}
+
+/// Takes the report as JSON, or as form fields when the `Content-Type` says so.
+async fn post_report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    with_request_id(&headers, async {
+        if is_form(&headers) {
+            return accept_form_report(&state, request).await;
+        }
+        match Json::<Report<WireTimestamp>>::from_request(request, &state).await {
+            Ok(Json(report)) => accept_wire_report(&state, report).await,
+            Err(rejection) => json_rejection_response(rejection),
+        }
+    })
+    .await
+}
+
+/// Same as `post_report`, but parses any body not declared as a form as JSON,
+/// whatever its `Content-Type`. Legacy firmware sends reports as `text/plain`.
+async fn post_report_lenient(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    with_request_id(&headers, async {
+        if is_form(&headers) {
+            return accept_form_report(&state, request).await;
+        }
+        let body = match Bytes::from_request(request, &state).await {
+            Ok(body) => body,
+            Err(rejection) => return body_rejection_response(rejection),
+        };
+        match Json::<Report<WireTimestamp>>::from_bytes(&body) {
+            Ok(Json(report)) => accept_wire_report(&state, report).await,
+            Err(rejection) => json_rejection_response(rejection),
+        }
+    })
+    .await
+}
+
+/// Body of a successful `POST /report` with `--report-response verbose`.
+#[derive(Debug, Serialize)]
+struct ReportAccepted {
+    accepted: bool,
+    pool: String,
+    server_time: u64,
+}
+
+/// Checks a single report's limits and forwards it.
+async fn accept_wire_report(state: &AppState, report: Report<WireTimestamp>) -> Response {
+    match report.validate() {
+        Ok(report) => accept_report(state, report).await,
+        Err(error) => invalid_timestamp_response(&error),
+    }
+}
+
+async fn accept_report(state: &AppState, report: Report) -> Response {
+    if let Err(error) = check_report_limits(state, &report) {
+        return report_limit_response(&error);
+    }
+    // Filled in before forwarding, which gives the report away.
+    let accepted = (state.report_response == ReportResponse::Verbose).then(|| ReportAccepted {
+        accepted: true,
+        pool: normalize_pool(state, report.pool.clone()).unwrap_or_default(),
+        server_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    });
+    let status = forward_report(state, report).await;
+    match accepted {
+        Some(accepted) if status == StatusCode::OK => (status, Json(accepted)).into_response(),
+        _ => status.into_response(),
+    }
+}
+
+/// Rejects reports without a pool when there is no `--default-pool` to fall back
+/// to, and identifiers longer than `--max-pool-name-len`/`--max-worker-id-len`.
+/// Both end up in every `/stats` response and as metric labels, so an oversized one
+/// costs far more than the report it came with. Lengths are in bytes.
+fn check_report_limits(state: &AppState, report: &Report) -> Result<(), String> {
+    if report.pool.is_none() && state.default_pool.is_none() {
+        return Err("report has no pool and no default pool is configured".to_string());
+    }
+    if report.pool().len() > state.max_pool_name_len {
+        return Err(format!(
+            "pool name is {} bytes long, the maximum is {}",
+            report.pool().len(),
+            state.max_pool_name_len
+        ));
+    }
+    // Pre-aggregated reports live under these worker ids, so a raw report must not
+    // be able to replace a collector's batch. Checked loosely enough to cover
+    // `--trim-worker-id` and `--lowercase-worker-id`.
+    if report.batch.is_none()
+        && report
+            .worker_id
+            .trim_start()
+            .get(..PREAGGREGATED_WORKER_PREFIX.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(PREAGGREGATED_WORKER_PREFIX))
+    {
+        return Err(format!(
+            "worker_id can't start with {PREAGGREGATED_WORKER_PREFIX:?}, which is reserved for \
+             pre-aggregated reports"
+        ));
+    }
+    if report.worker_id.len() > state.max_worker_id_len {
+        return Err(format!(
+            "worker_id is {} bytes long, the maximum is {}",
+            report.worker_id.len(),
+            state.max_worker_id_len
+        ));
+    }
+    if state.expired_reports == ExpiredReports::Reject && expired_on_arrival(state, report) {
+        state.expired_on_arrival.fetch_add(1, Ordering::Relaxed);
+        return Err(format!(
+            "report with timestamp {} has already expired",
+            report.timestamp
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a report is already past its expiry against the server clock, so the
+/// data actor would just prune it again on the next tick.
+fn expired_on_arrival(state: &AppState, report: &Report) -> bool {
+    let config = &state.data_actor_config;
+    if config.window_mode != WindowMode::Sliding
+        || config.expire_relative_to != ExpireRelativeTo::Server
+    {
+        return false;
+    }
+    let now_ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    report.expires_at(config) < now_ts
+}
+
+/// A `timestamp` string that isn't RFC3339, which clients can fix by sending UNIX
+/// seconds instead.
+fn invalid_timestamp_response(error: &str) -> Response {
+    let body = ErrorBody {
+        error,
+        code: Some("invalid_timestamp"),
+        path: None,
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+fn report_limit_response(error: &str) -> Response {
+    let body = ErrorBody {
+        error,
+        code: None,
+        path: None,
+    };
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// Per-message reply on the report WebSocket.
+#[derive(Debug, Serialize)]
+struct WsAck {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn report_ws(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| handle_report_ws(socket, state))
+}
+
+/// Reads one JSON `Report` per text message and acks each one. The next message is
+/// only read once the current report is in the channel, so a full channel pauses
+/// the socket instead of buffering without bound.
+async fn handle_report_ws(mut socket: WebSocket, state: AppState) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let payload = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            // Pings are answered by axum; binary and pong frames carry no reports.
+            _ => continue,
+        };
+
+        let ack = match serde_json::from_str::<Report>(payload.as_str()) {
+            Ok(report) if let Err(error) = check_report_limits(&state, &report) => WsAck {
+                ok: false,
+                error: Some(error),
+            },
+            Ok(report) => match forward_report(&state, report).await {
+                StatusCode::OK => WsAck {
+                    ok: true,
+                    error: None,
+                },
+                _ => WsAck {
+                    ok: false,
+                    error: Some("internal error".to_string()),
+                },
+            },
+            Err(err) => WsAck {
+                ok: false,
+                error: Some(err.to_string()),
+            },
+        };
+
+        let Ok(ack) = serde_json::to_string(&ack) else {
+            break;
+        };
+        if socket.send(Message::Text(ack.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn post_reports(
+    State(state): State<AppState>,
+    reports: Result<Json<Vec<Report<WireTimestamp>>>, JsonRejection>,
+) -> Response {
+    let reports = match reports {
+        Ok(Json(reports)) => reports,
+        Err(rejection) => return json_rejection_response(rejection),
+    };
+    let reports = match reports
+        .into_iter()
+        .map(Report::validate)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(reports) => reports,
+        Err(error) => return invalid_timestamp_response(&error),
+    };
+    if reports.len() > state.max_batch_size {
+        return report_limit_response(&format!(
+            "Batch of {} reports exceeds the maximum of {}",
+            reports.len(),
+            state.max_batch_size
+        ));
+    }
+    // Check the whole batch up front, so a bad report doesn't leave it half applied.
+    for report in &reports {
+        if let Err(error) = check_report_limits(&state, report) {
+            return report_limit_response(&error);
+        }
+    }
+
+    // Forward in chunks and yield in between, so a single huge batch can't hog
+    // the worker thread while other connections wait. The data actor takes reports
+    // in order, so with `--ack-mode processed` only the last one has to be awaited.
+    let mut reports = reports.into_iter().peekable();
+    let mut forwarded = 0;
+    while let Some(report) = reports.next() {
+        let status = if reports.peek().is_some() {
+            enqueue_report(&state, report, None).await
+        } else {
+            forward_report(&state, report).await
+        };
+        if status != StatusCode::OK {
+            return status.into_response();
+        }
+        forwarded += 1;
+        if forwarded % BATCH_YIELD_EVERY == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    StatusCode::OK.into_response()
+}
+
+/// Sub-stats of a pool that an edge collector computed from its own miners' raw
+/// reports, for `POST /preaggregated`.
+#[derive(Debug, Deserialize)]
+struct PreAggregatedReport {
+    pool: String,
+    worker_count: u64,
+    total_hashrate: f64,
+    total_temp: f64,
+    report_count: u64,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    timestamp: WireTimestamp,
+    /// Tells apart collectors feeding the same pool, whose workers then add up.
+    #[serde(default)]
+    collector: Option<String>,
+}
+
+/// Worker ids of pre-aggregated reports are this plus the collector, so each
+/// collector's latest `worker_count` stands in for its workers.
+const PREAGGREGATED_WORKER_PREFIX: &str = "preaggregated:";
+
+/// Accepts a collector's pre-aggregated batch. It is kept as a single report with
+/// the batch's mean hashrate and temperature that counts as `report_count` reports
+/// in every average, sum and rate, so combined with raw reports of the same pool
+/// the averages come out as if the collector had forwarded every raw report. As its
+/// collector's latest report it counts as `worker_count` workers, but joining and
+/// leaving it is one worker, and percentiles see it as one sample.
+async fn post_preaggregated(
+    State(state): State<AppState>,
+    batch: Result<Json<PreAggregatedReport>, JsonRejection>,
+) -> Response {
+    let batch = match batch {
+        Ok(Json(batch)) => batch,
+        Err(rejection) => return json_rejection_response(rejection),
+    };
+    let timestamp = match batch.timestamp.secs() {
+        Ok(timestamp) => timestamp,
+        Err(error) => return invalid_timestamp_response(&error),
+    };
+    if batch.report_count == 0 || batch.worker_count == 0 {
+        return report_limit_response("report_count and worker_count must be positive");
+    }
+    if batch.worker_count > batch.report_count {
+        return report_limit_response("worker_count can't exceed report_count");
+    }
+    // It weighs as that many reports, so it is capped like a batch of them.
+    if batch.report_count > state.max_batch_size as u64 {
+        return report_limit_response(&format!(
+            "report_count of {} exceeds the maximum of {}",
+            batch.report_count, state.max_batch_size
+        ));
+    }
+    if [batch.total_hashrate, batch.total_temp]
+        .iter()
+        .any(|total| !total.is_finite() || *total < 0.0)
+    {
+        return report_limit_response(
+            "total_hashrate and total_temp must be finite and not negative",
+        );
+    }
+    let report = Report {
+        worker_id: format!(
+            "{PREAGGREGATED_WORKER_PREFIX}{}",
+            batch.collector.unwrap_or_default()
+        ),
+        pool: Some(batch.pool),
+        hashrate: batch.total_hashrate / batch.report_count as f64,
+        temperature: batch.total_temp / batch.report_count as f64,
+        timestamp,
+        ttl_secs: None,
+        power: None,
+        region: None,
+        received_at: None,
+        batch: Some(Batch {
+            reports: batch.report_count,
+            workers: batch.worker_count,
+        }),
+    };
+    accept_report(&state, report).await
+}
+
+/// Like the rejection's own response, except that a body which failed to buffer
+/// for a reason other than its size limit is reported as `incomplete_body`: the
+/// client's connection dropped mid-upload, so resending the same payload can work.
+fn body_rejection_response(rejection: BytesRejection) -> Response {
+    match rejection {
+        BytesRejection::FailedToBufferBody(FailedToBufferBody::UnknownBodyError(err)) => {
+            debug!(error = %err, "Request body ended before it was complete");
+            let body = ErrorBody {
+                error: "request body ended before it was complete, retry the request",
+                code: Some("incomplete_body"),
+                path: None,
+            };
+            (StatusCode::BAD_REQUEST, Json(body)).into_response()
+        }
+        other => other.into_response(),
+    }
+}
+
+fn json_rejection_response(rejection: JsonRejection) -> Response {
+    match rejection {
+        JsonRejection::BytesRejection(rejection) => body_rejection_response(rejection),
+        other => {
+            let error = other.body_text();
+            let body = ErrorBody {
+                error: &error,
+                code: None,
+                path: None,
+            };
+            (other.status(), Json(body)).into_response()
+        }
+    }
+}
+
+fn form_rejection_response(rejection: FormRejection) -> Response {
+    match rejection {
+        FormRejection::BytesRejection(rejection) => body_rejection_response(rejection),
+        other => {
+            let error = other.body_text();
+            let body = ErrorBody {
+                error: &error,
+                code: None,
+                path: None,
+            };
+            (other.status(), Json(body)).into_response()
+        }
+    }
+}
+
+/// Falls back to `--default-pool` and applies `--normalize-pool-case`.
+fn normalize_pool(state: &AppState, pool: Option<String>) -> Option<String> {
+    pool.or_else(|| state.default_pool.clone())
+        .map(|pool| state.pool_case.apply(pool))
+}
+
+fn normalize_worker_id(state: &AppState, worker_id: String) -> String {
+    if !state.trim_worker_id {
+        return worker_id;
+    }
+    let trimmed = worker_id.trim();
+    if state.lowercase_worker_id {
+        trimmed.to_lowercase()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Hands a report to the data actor and, with `--ack-mode processed`, waits until
+/// it has been added to its pool.
+async fn forward_report(state: &AppState, report: Report) -> StatusCode {
+    if state.ack_mode == AckMode::Queued {
+        return enqueue_report(state, report, None).await;
+    }
+
+    let (processed_tx, processed_rx) = oneshot::channel();
+    let status = enqueue_report(state, report, Some(processed_tx)).await;
+    if status != StatusCode::OK {
+        return status;
+    }
+    match tokio::time::timeout(PROCESSED_ACK_TIMEOUT, processed_rx).await {
+        Ok(Ok(())) => StatusCode::OK,
+        Ok(Err(_)) => {
+            error!("Data actor dropped a report without processing it");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        Err(_) => {
+            warn!("Report was queued but not processed within the ack timeout");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// The single entry point of every ingest path into the data actor.
+async fn enqueue_report(
+    state: &AppState,
+    mut report: Report,
+    processed_tx: Option<oneshot::Sender<()>>,
+) -> StatusCode {
+    if state.expired_reports == ExpiredReports::Drop && expired_on_arrival(state, &report) {
+        state.expired_on_arrival.fetch_add(1, Ordering::Relaxed);
+        // Dropping it is all the processing it gets.
+        if let Some(processed_tx) = processed_tx {
+            processed_tx.send(()).ok();
+        }
+        return StatusCode::OK;
+    }
+
+    let now_ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    report.timestamp = clamp_timestamp(report.timestamp, now_ts);
+    report.received_at = Some(Instant::now());
+    report.pool = normalize_pool(state, report.pool);
+    report.worker_id = normalize_worker_id(state, report.worker_id);
+
+    // Skip the clone entirely while nobody is watching the debug stream.
+    if state.debug_tx.receiver_count() > 0 {
+        state.debug_tx.send(report.clone()).ok();
+    }
+
+    let weight = report.weight();
+    let queued = QueuedReport {
+        report,
+        processed_tx,
+    };
+    if state.report_tx.send(queued).await.is_err() {
+        error!("Report channel is closed. This is a critical internal error.");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.reports_ingested.fetch_add(weight, Ordering::Relaxed);
+    StatusCode::OK
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum StatsFormat {
+    /// `{"pools": {"<name>": {...}}, ...}`, served straight from the cached JSON.
+    #[default]
+    Map,
+    /// `[{"pool": "<name>", ...}]`, sorted by pool name.
+    Array,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct StatsQuery {
+    #[serde(default)]
+    format: StatsFormat,
+    // Comma-separated pool names to limit the response to.
+    pools: Option<String>,
+    // Only pools whose `avg_temp` is at least this.
+    min_temp: Option<f64>,
+}
+
+/// One element of the `?format=array` output.
+#[derive(Serialize)]
+struct PoolEntry<'a, S> {
+    pool: &'a str,
+    #[serde(flatten)]
+    stats: S,
+}
+
+async fn get_stats(State(state): State<AppState>, Query(query): Query<StatsQuery>) -> Response {
+    let snapshot = state.stats_rx.borrow().clone();
+    // Filters combine: a pool has to pass every one given.
+    let wanted: Option<HashSet<String>> = query.pools.as_deref().map(|pools| {
+        pools
+            .split(',')
+            .map(|pool| state.pool_case.apply(pool.trim().to_string()))
+            .collect()
+    });
+    let filtered = (wanted.is_some() || query.min_temp.is_some()).then(|| {
+        snapshot.stats.filter_pools(|pool, stats| {
+            wanted.as_ref().is_none_or(|wanted| wanted.contains(pool))
+                && query
+                    .min_temp
+                    .is_none_or(|min_temp| stats.avg_temp >= min_temp)
+        })
+    });
+    let max_pools = state.data_actor_config.max_pools_in_response;
+    match (query.format, filtered) {
+        (StatsFormat::Map, None) => {
+            (STATS_RESPONSE_HEADERS.clone(), snapshot.json.clone()).into_response()
+        }
+        (StatsFormat::Map, Some(filtered)) => {
+            let served = filtered.truncated(max_pools).unwrap_or(filtered);
+            match state.data_actor_config.output_profile {
+                OutputProfile::Default => {
+                    (STATS_RESPONSE_HEADERS.clone(), Json(&served)).into_response()
+                }
+                OutputProfile::Legacy => {
+                    let legacy = LegacyAllStats::from(&served);
+                    (STATS_RESPONSE_HEADERS.clone(), Json(legacy)).into_response()
+                }
+            }
+        }
+        (StatsFormat::Array, filtered) => {
+            let stats = filtered.as_ref().unwrap_or(&snapshot.stats);
+            // An array has nowhere to put `truncated`, so the count goes in a header.
+            let truncated = stats.truncated(max_pools);
+            let mut headers = STATS_RESPONSE_HEADERS.clone();
+            if let Some(total_pools) = truncated.as_ref().and_then(|stats| stats.total_pools) {
+                headers.insert("x-total-pools", HeaderValue::from(total_pools));
+            }
+            let pools = truncated.as_ref().unwrap_or(stats).pools.iter();
+            match state.data_actor_config.output_profile {
+                OutputProfile::Default => {
+                    let entries: Vec<_> = pools
+                        .map(|(pool, stats)| PoolEntry { pool, stats })
+                        .collect();
+                    (headers, Json(entries)).into_response()
+                }
+                OutputProfile::Legacy => {
+                    let entries: Vec<_> = pools
+                        .map(|(pool, stats)| PoolEntry {
+                            pool,
+                            stats: LegacyPoolStats::from(stats),
+                        })
+                        .collect();
+                    (headers, Json(entries)).into_response()
+                }
+            }
+        }
+    }
+}
+
+/// The `/stats` JSON as a gzip'd file download, for offline analysis. Every pool is
+/// included, whatever `--max-pools-in-response` cuts `/stats` down to.
+async fn download_stats(State(state): State<AppState>) -> Response {
+    let snapshot = state.stats_rx.borrow().clone();
+    let json =
+        match PublishedStats::to_json(&snapshot.stats, state.data_actor_config.output_profile) {
+            Ok(json) => json,
+            Err(err) => {
+                error!(error = %err, "Failed to serialize the stats download");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed = encoder
+        .write_all(json.as_bytes())
+        .and_then(|()| encoder.finish());
+    match compressed {
+        Ok(body) => (
+            [
+                (header::CONTENT_TYPE, "application/gzip"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"stats.json.gz\"",
+                ),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(err) => {
+            error!(error = %err, "Failed to compress the stats download");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The headline numbers of the current snapshot, for status pages that don't need
+/// the per-pool map.
+#[derive(Debug, Serialize)]
+struct Summary {
+    // Stale pools are left out of every field, as they are of `/stats`' total.
+    pools: usize,
+    workers: usize,
+    // The pools' `pool_hashrate`, summed.
+    total_hashrate: f64,
+    // The pools' `max_temp`, so left out with `--incremental-stats`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_temp: Option<f64>,
+}
+
+impl Summary {
+    fn new(stats: &AllStats) -> Self {
+        let live_pools = || stats.pools.values().filter(|stats| !stats.stale);
+        Self {
+            pools: live_pools().count(),
+            // Distinct workers, like `/stats`' total, rather than a sum over pools.
+            workers: stats.total.workers,
+            total_hashrate: live_hashrate(stats.pools.values()),
+            max_temp: live_pools()
+                .filter_map(|stats| stats.max_temp)
+                .reduce(f64::max),
+        }
+    }
+}
+
+async fn get_summary(State(state): State<AppState>) -> impl IntoResponse {
+    let snapshot = state.stats_rx.borrow().clone();
+    (
+        STATS_RESPONSE_HEADERS.clone(),
+        Json(Summary::new(&snapshot.stats)),
+    )
+}
+
+/// What the pools are mining at together: their `pool_hashrate`, summed over the
+/// ones that aren't stale. Shared by the hashrate shares, `/summary` and `/status`.
+fn live_hashrate<'a>(pools: impl IntoIterator<Item = &'a PoolStats>) -> f64 {
+    pools
+        .into_iter()
+        .filter(|stats| !stats.stale)
+        .map(|stats| stats.pool_hashrate)
+        .sum()
+}
+
+/// `hashrate` in H/s with the largest SI suffix that keeps it at 1 or more, to three
+/// significant digits where the magnitude allows: `12.3 TH/s`, `950 H/s`.
+fn format_hashrate(hashrate: f64) -> String {
+    const UNITS: [&str; 6] = ["H/s", "KH/s", "MH/s", "GH/s", "TH/s", "PH/s"];
+
+    if !hashrate.is_finite() {
+        return format!("{hashrate} H/s");
+    }
+    let mut value = hashrate;
+    let mut unit = 0;
+    // 999.5 rather than 1000, so values that round up to 1000 move to the next unit.
+    while value.abs() >= 999.5 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    let decimals = match value.abs() {
+        v if v < 10.0 => 2,
+        v if v < 100.0 => 1,
+        _ => 0,
+    };
+    format!("{value:.decimals$} {}", UNITS[unit])
+}
+
+/// The current snapshot as plain text for status pages, one line per pool and a
+/// total, with hashrates in human-readable units. `/stats` stays the numeric API.
+async fn get_status(State(state): State<AppState>) -> impl IntoResponse {
+    use std::fmt::Write;
+
+    let snapshot = state.stats_rx.borrow().clone();
+    let mut out = String::new();
+    for (pool, stats) in &snapshot.stats.pools {
+        write!(
+            out,
+            "{pool}: {}, {} workers, avg temp {}",
+            format_hashrate(stats.pool_hashrate),
+            stats.workers,
+            stats.avg_temp
+        )
+        .ok();
+        if stats.stale {
+            out.push_str(" (stale)");
+        }
+        out.push('\n');
+    }
+    writeln!(
+        out,
+        "total: {}, {} workers",
+        format_hashrate(live_hashrate(snapshot.stats.pools.values())),
+        snapshot.stats.total.workers
+    )
+    .ok();
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], out)
+}
+
+async fn get_stats_by_region(State(state): State<AppState>) -> impl IntoResponse {
+    let snapshot = state.stats_rx.borrow().clone();
+    (
+        STATS_RESPONSE_HEADERS.clone(),
+        Json(snapshot.stats.regions.clone()),
+    )
+}
+
+/// Every effective setting: the CLI flags (defaults included) plus the values that
+/// are fixed in code. Secrets are redacted.
+#[derive(Debug, Serialize)]
+struct EffectiveConfig<'a> {
+    bind_address: &'a str,
+    recalc_interval_secs: f64,
+    #[serde(flatten)]
+    cli: &'a Cli,
+}
+
+/// Response of `GET /info`, a quick check that the instance is alive and busy.
+#[derive(Debug, Serialize)]
+struct Info {
+    version: &'static str,
+    // Accepted reports since startup; reports dropped as expired don't count.
+    total_reports_ingested: u64,
+}
+
+async fn get_info(State(state): State<AppState>) -> impl IntoResponse {
+    let info = Info {
+        version: env!("CARGO_PKG_VERSION"),
+        total_reports_ingested: state.reports_ingested.load(Ordering::Relaxed),
+    };
+    (STATS_RESPONSE_HEADERS.clone(), Json(info))
+}
+
+/// 503 once the data actor has died, so orchestration replaces an instance that
+/// would otherwise keep serving its last stats forever.
+async fn readyz(State(state): State<AppState>) -> StatusCode {
+    if state.data_actor_alive.load(Ordering::Relaxed) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+async fn get_config(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        STATS_RESPONSE_HEADERS.clone(),
+        state.config_json.to_string(),
+    )
+}
+
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let metrics = render_metrics(
+        &state.stats_rx.borrow().stats,
+        state.expired_on_arrival.load(Ordering::Relaxed),
+    );
+    (METRICS_RESPONSE_HEADERS.clone(), metrics)
+}
+
+/// A per-pool Prometheus gauge derived from `PoolStats`.
+struct PoolGauge {
+    name: &'static str,
+    help: &'static str,
+    value: fn(&PoolStats) -> f64,
+}
+
+const POOL_GAUGES: &[PoolGauge] = &[
+    PoolGauge {
+        name: "miner_pool_workers",
+        help: "Unique workers reporting in the window.",
+        value: |p| p.workers as f64,
+    },
+    PoolGauge {
+        name: "miner_pool_avg_hashrate",
+        help: "Average hashrate over the window.",
+        value: |p| p.avg_hashrate,
+    },
+    PoolGauge {
+        name: "miner_pool_avg_temp",
+        help: "Average temperature over the window.",
+        value: |p| p.avg_temp,
+    },
+    PoolGauge {
+        name: "miner_pool_reports_per_sec",
+        help: "Reports received per second.",
+        value: |p| p.reports_per_sec,
+    },
+];
+
+/// Renders a stats snapshot in the Prometheus text exposition format, along with
+/// `AppState::expired_on_arrival`, which isn't part of the snapshot.
+fn render_metrics(stats: &AllStats, expired_on_arrival: u64) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    for gauge in POOL_GAUGES {
+        writeln!(out, "# HELP {} {}", gauge.name, gauge.help).ok();
+        writeln!(out, "# TYPE {} gauge", gauge.name).ok();
+        for (pool, pool_stats) in &stats.pools {
+            let pool = escape_label_value(pool);
+            let value = (gauge.value)(pool_stats);
+            writeln!(out, "{}{{pool=\"{pool}\"}} {value}", gauge.name).ok();
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP miner_reports_per_sec Reports received per second across all pools."
+    )
+    .ok();
+    writeln!(out, "# TYPE miner_reports_per_sec gauge").ok();
+    writeln!(out, "miner_reports_per_sec {}", stats.total.reports_per_sec).ok();
+    writeln!(
+        out,
+        "# HELP miner_reports_unique_workers Distinct workers across all pools."
+    )
+    .ok();
+    writeln!(out, "# TYPE miner_reports_unique_workers gauge").ok();
+    writeln!(out, "miner_reports_unique_workers {}", stats.total.workers).ok();
+    writeln!(
+        out,
+        "# HELP miner_reports_expired_on_arrival_total Reports rejected or dropped for having expired before they arrived."
+    )
+    .ok();
+    writeln!(out, "# TYPE miner_reports_expired_on_arrival_total counter").ok();
+    writeln!(
+        out,
+        "miner_reports_expired_on_arrival_total {expired_on_arrival}"
+    )
+    .ok();
+    out
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+async fn get_pools(State(state): State<AppState>) -> impl IntoResponse {
+    // BTreeMap keys are already sorted by pool name.
+    let pools: Vec<String> = state
+        .stats_rx
+        .borrow()
+        .stats
+        .pools
+        .keys()
+        .cloned()
+        .collect();
+    Json(pools)
+}
+
+/// JSON body of error responses, so clients get JSON on every response.
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    // Machine-readable reason, for errors a client is expected to act on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+}
+
+async fn not_found(uri: Uri) -> impl IntoResponse {
+    let body = ErrorBody {
+        error: "not found",
+        code: None,
+        path: Some(uri.path()),
+    };
+    (StatusCode::NOT_FOUND, Json(body)).into_response()
+}
+
+/// Streams every ingested report as NDJSON. A consumer that falls more than
+/// `DEBUG_STREAM_CAPACITY` reports behind is disconnected; ingest never waits for it.
+async fn debug_stream(State(state): State<AppState>) -> Response {
+    let debug_rx = state.debug_tx.subscribe();
+    let lines = futures::stream::unfold(debug_rx, |mut debug_rx| async move {
+        loop {
+            match debug_rx.recv().await {
+                Ok(report) => {
+                    let Ok(mut line) = serde_json::to_vec(&report) else {
+                        continue;
+                    };
+                    line.push(b'\n');
+                    return Some((
+                        Ok::<_, std::convert::Infallible>(Bytes::from(line)),
+                        debug_rx,
+                    ));
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(
+                        skipped,
+                        "Debug stream consumer lagged behind, disconnecting it"
+                    );
+                    return None;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct HistogramQuery {
+    /// Comma-separated bucket edges, e.g. `0,50,100,150`.
+    buckets: String,
+}
+
+/// Parses at least two finite, strictly increasing bucket edges.
+fn parse_bucket_edges(buckets: &str) -> Result<Vec<f64>, &'static str> {
+    let edges = buckets
+        .split(',')
+        .map(|edge| edge.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "bucket edges must be comma-separated numbers")?;
+
+    if edges.len() < 2 {
+        return Err("at least two bucket edges are required");
+    }
+    if edges.iter().any(|edge| !edge.is_finite()) {
+        return Err("bucket edges must be finite");
+    }
+    if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("bucket edges must be strictly increasing");
+    }
+    Ok(edges)
+}
+
+async fn get_hashrate_histogram(
+    State(state): State<AppState>,
+    Path(pool): Path<String>,
+    Query(query): Query<HistogramQuery>,
+) -> Response {
+    let edges = match parse_bucket_edges(&query.buckets) {
+        Ok(edges) => edges,
+        Err(error) => {
+            let body = ErrorBody {
+                error,
+                code: None,
+                path: None,
+            };
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+    };
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let command = DataActorCommand::HashrateHistogram {
+        pool: state.pool_case.apply(pool),
+        edges,
+        reply_tx,
+    };
+    if state.command_tx.send(command).await.is_err() {
+        error!("Command channel is closed. This is a critical internal error.");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match reply_rx.await {
+        Ok(Some(histogram)) => Json(histogram).into_response(),
+        Ok(None) => {
+            let body = ErrorBody {
+                error: "unknown pool",
+                code: None,
+                path: None,
+            };
+            (StatusCode::NOT_FOUND, Json(body)).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// A worker's latest report in each pool it appears in. Worker IDs aren't indexed,
+/// so this scans every retained report: O(all reports), on the data actor's task.
+async fn get_worker(State(state): State<AppState>, Path(worker_id): Path<String>) -> Response {
+    let worker_id = normalize_worker_id(&state, worker_id);
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let command = DataActorCommand::WorkerStats {
+        worker_id: worker_id.clone(),
+        reply_tx,
+    };
+    if state.command_tx.send(command).await.is_err() {
+        error!("Command channel is closed. This is a critical internal error.");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let Ok(pools) = reply_rx.await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    if pools.is_empty() {
+        let body = ErrorBody {
+            error: "unknown worker",
+            code: None,
+            path: None,
+        };
+        return (StatusCode::NOT_FOUND, Json(body)).into_response();
+    }
+
+    let total_hashrate = pools.values().map(|stats| stats.hashrate).sum();
+    let avg_temp = pools.values().map(|stats| stats.temperature).sum::<f64>() / pools.len() as f64;
+    Json(WorkerStats {
+        worker_id,
+        pools,
+        total_hashrate,
+        avg_temp,
+    })
+    .into_response()
+}
+
+/// What a `--auth-file` token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Scope {
+    Read,
+    Write,
+}
+
+/// The `--auth-file` contents: token -> the scopes it grants.
+type AuthTokens = HashMap<String, HashSet<Scope>>;
+
+fn load_auth_file(path: &FsPath) -> Result<AuthTokens> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read --auth-file {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse --auth-file {}", path.display()))
+}
+
+/// Whether an `Authorization` header value carries a token granted `scope`.
+fn has_scope(tokens: &AuthTokens, authorization: Option<&str>, scope: Scope) -> bool {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| tokens.get(token))
+        .is_some_and(|scopes| scopes.contains(&scope))
+}
+
+async fn require_scope(
+    State((tokens, scope)): State<(Arc<AuthTokens>, Scope)>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !has_scope(&tokens, authorization, scope) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+async fn require_admin_token(
+    State(token): State<Arc<str>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| provided == &*token);
+
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn disable_pool(State(state): State<AppState>, Path(pool): Path<String>) -> StatusCode {
+    set_pool_enabled(&state, pool, false).await
+}
+
+async fn enable_pool(State(state): State<AppState>, Path(pool): Path<String>) -> StatusCode {
+    set_pool_enabled(&state, pool, true).await
+}
+
+async fn set_pool_enabled(state: &AppState, pool: String, enabled: bool) -> StatusCode {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let command = DataActorCommand::SetPoolEnabled {
+        pool: state.pool_case.apply(pool),
+        enabled,
+        reply_tx,
+    };
+
+    if state.command_tx.send(command).await.is_err() {
+        error!("Command channel is closed. This is a critical internal error.");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match reply_rx.await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Discards all pools and reports. Runs on the data actor between ticks like every
+/// other command, so the next published snapshot is the first one after the reset.
+async fn reset_stats(State(state): State<AppState>) -> StatusCode {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if state
+        .command_tx
+        .send(DataActorCommand::Reset { reply_tx })
+        .await
+        .is_err()
+    {
+        error!("Command channel is closed. This is a critical internal error.");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match reply_rx.await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Starts the same graceful shutdown as SIGTERM, for environments that can't send
+/// signals. Answers before the shutdown is under way, so the reply isn't cut off.
+async fn request_shutdown(State(state): State<AppState>) -> StatusCode {
+    info!("Shutdown requested over HTTP");
+    // Stores a permit if `shutdown_signal` isn't waiting yet, so it can't be missed.
+    state.shutdown_requested.notify_one();
+    StatusCode::ACCEPTED
+}
+
+/// Pushes every newly published snapshot to a Prometheus Pushgateway. Runs on its
+/// own task, so a slow or unreachable gateway never delays the data actor; while a
+/// push is in flight, intermediate snapshots are simply skipped.
+async fn pushgateway_pusher(
+    mut stats_rx: watch::Receiver<Arc<PublishedStats>>,
+    expired_on_arrival: Arc<AtomicU64>,
+    base_url: String,
+) {
+    let url = format!(
+        "{}/metrics/job/miner_reports",
+        base_url.trim_end_matches('/')
+    );
+    let client = reqwest::Client::new();
+
+    while stats_rx.changed().await.is_ok() {
+        let body = render_metrics(
+            &stats_rx.borrow_and_update().stats,
+            expired_on_arrival.load(Ordering::Relaxed),
+        );
+        // PUT replaces the whole group, so pools that disappeared are dropped too.
+        let result = client
+            .put(&url)
+            .body(body)
+            .timeout(PUSH_TIMEOUT)
+            .send()
+            .await;
+        match result.and_then(|response| response.error_for_status()) {
+            Ok(_) => {}
+            Err(err) => warn!(url = %url, error = %err, "Failed to push metrics"),
+        }
+    }
+}
+
+fn parse_smoothing_weight(value: &str) -> Result<f64, String> {
+    let weight: f64 = value
+        .parse()
+        .map_err(|_| format!("{value:?} is not a number"))?;
+    if !(weight > 0.0 && weight <= 1.0) {
+        return Err("weight must be greater than 0 and at most 1".to_string());
+    }
+    Ok(weight)
+}
+
+/// Parses a `--pool-temp-alert-threshold` value of the form `POOL=THRESHOLD`.
+fn parse_pool_threshold(value: &str) -> Result<(String, f64), String> {
+    let (pool, threshold) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected POOL=THRESHOLD, got {value:?}"))?;
+    let threshold: f64 = threshold
+        .trim()
+        .parse()
+        .map_err(|_| format!("threshold {threshold:?} is not a number"))?;
+    if !threshold.is_finite() {
+        return Err("threshold must be finite".to_string());
+    }
+    Ok((pool.to_string(), threshold))
+}
+
+/// Calculates the stats of an already pruned set of reports (a pool, or a region
+/// across pools) and records its current workers for the churn calculation on the
+/// next tick.
+fn compute_pool_stats<'a>(
+    name: &str,
+    reports: impl Iterator<Item = &'a Report> + Clone,
+    reports_per_sec: f64,
+    now_ts: u64,
+    previous_workers: &mut HashSet<String>,
+    config: &DataActorConfig,
+) -> PoolStats {
+    // With `--per-second-dedup`, only the last received report of each worker and
+    // second counts, so a worker reporting twice in a second isn't weighted double.
+    let last_in_second: Option<HashMap<(&str, u64), usize>> = config.per_second_dedup.then(|| {
+        reports
+            .clone()
+            .enumerate()
+            .map(|(index, report)| ((report.worker_id.as_str(), report.timestamp), index))
+            .collect()
+    });
+    let reports = reports
+        .enumerate()
+        .filter(|(index, report)| {
+            last_in_second
+                .as_ref()
+                .is_none_or(|last| last[&(report.worker_id.as_str(), report.timestamp)] == *index)
+        })
+        .map(|(_, report)| report);
+
+    // With `--max-reports-per-worker-per-window`, only each worker's most recently
+    // received reports count, which bounds the weight of a worker that reports often.
+    let within_cap: Option<HashSet<usize>> = config.max_reports_per_worker.map(|max| {
+        let workers: Vec<&str> = reports
+            .clone()
+            .map(|report| report.worker_id.as_str())
+            .collect();
+        let mut counted: HashMap<&str, usize> = HashMap::new();
+        (0..workers.len())
+            .rev()
+            .filter(|&index| {
+                let count = counted.entry(workers[index]).or_default();
+                *count += 1;
+                *count <= max.get()
+            })
+            .collect()
+    });
+    let reports = reports
+        .enumerate()
+        .filter(|(index, _)| within_cap.as_ref().is_none_or(|kept| kept.contains(index)))
+        .map(|(_, report)| report);
+
+    // A pre-aggregated batch counts as all the reports it stands for.
+    let report_count: usize = reports.clone().map(|report| report.weight() as usize).sum();
+
+    // Indexed like `reports`.
+    let filtered_temps = config
+        .temp_median_window
+        .map(|window| median_filtered_temps(reports.clone(), window));
+    let temperature = |index: usize, report: &Report| {
+        filtered_temps
+            .as_ref()
+            .map_or(report.temperature, |temps| temps[index])
+    };
+
+    // Step 1: Calculate all required values in a single pass using fold.
+    let (total_hashrate, total_temp, zero_hashrate_reports, unique_workers) =
+        reports.clone().enumerate().fold(
+            // The initial state of our accumulator:
+            // (hash, temp, zero hashrate reports,
+            //  worker -> (latest timestamp, its hashrate, the workers it stands for))
+            (0.0, 0.0, 0usize, HashMap::new()),
+            // The closure to update the accumulator for each report
+            |(h_acc, t_acc, zero_acc, mut workers), (index, report)| {
+                let current = (report.timestamp, report.hashrate, report.workers());
+                let latest = workers.entry(&report.worker_id).or_insert(current);
+                if report.timestamp >= latest.0 {
+                    *latest = current;
+                }
+                let weight = report.weight();
+                let zero = if report.hashrate == 0.0 {
+                    weight as usize
+                } else {
+                    0
+                };
+                (
+                    h_acc + report.hashrate * weight as f64,
+                    t_acc + temperature(index, report) * weight as f64,
+                    zero_acc + zero,
+                    workers,
+                )
+            },
+        );
+    let worker_count: u64 = unique_workers
+        .values()
+        .map(|&(_, _, workers)| workers)
+        .sum();
+    // A batch's hashrate is the mean over the workers it stands for. With
+    // `--avg-window-secs`, a worker silent for longer than that is taken to be down.
+    let pool_hashrate: f64 = unique_workers
+        .values()
+        .filter(|(latest, _, _)| {
+            config
+                .avg_window_secs
+                .is_none_or(|avg_window_secs| latest.saturating_add(avg_window_secs) >= now_ts)
+        })
+        .map(|&(_, hashrate, workers)| hashrate * workers as f64)
+        .sum();
+    // With `--avg-window-secs`, the averages only cover the most recent reports, which
+    // takes a second pass over them.
+    let (avg_hashrate_sum, avg_temp_sum, avg_reports, avg_zero_hashrate_reports) =
+        match config.avg_window_secs {
+            None => (
+                total_hashrate,
+                total_temp,
+                report_count,
+                zero_hashrate_reports,
+            ),
+            Some(avg_window_secs) => reports
+                .clone()
+                .enumerate()
+                .filter(|(_, report)| report.timestamp.saturating_add(avg_window_secs) >= now_ts)
+                .fold(
+                    (0.0, 0.0, 0, 0),
+                    |(h_acc, t_acc, n, zero), (index, report)| {
+                        let weight = report.weight() as usize;
+                        (
+                            h_acc + report.hashrate * weight as f64,
+                            t_acc + temperature(index, report) * weight as f64,
+                            n + weight,
+                            zero + if report.hashrate == 0.0 { weight } else { 0 },
+                        )
+                    },
+                ),
+        };
+    // Zero hashrates add nothing to the sum, so leaving them out only shrinks the
+    // report count the average is taken over.
+    let hashrate_reports = if config.exclude_zero_hashrate {
+        avg_reports - avg_zero_hashrate_reports
+    } else {
+        avg_reports
+    };
+    let zero_hashrate_workers = unique_workers
+        .values()
+        .filter(|(_, hashrate, _)| *hashrate == 0.0)
+        .map(|&(_, _, workers)| workers as usize)
+        .sum();
+
+    // Client timestamps aren't guaranteed to arrive in order, so the front of the
+    // deque isn't necessarily the oldest report.
+    let oldest_timestamp = reports.clone().map(|report| report.timestamp).min();
+    let oldest_report_age_secs =
+        oldest_timestamp.map_or(0, |timestamp| now_ts.saturating_sub(timestamp));
+    let max_temp = reports
+        .clone()
+        .enumerate()
+        .map(|(index, report)| temperature(index, report))
+        .filter(|temp| temp.is_finite())
+        .reduce(f64::max);
+
+    // Medians ignore a single stuck sensor, but need a sort, so they are opt-in.
+    // With `--percentile-mode tdigest` the data actor fills them in instead.
+    let exact_percentiles = config.percentiles && config.percentile_mode == PercentileMode::Exact;
+    let temps = exact_percentiles.then(|| {
+        let mut temps: Vec<f64> = reports
+            .clone()
+            .map(|report| report.temperature)
+            .filter(|temp| temp.is_finite())
+            .collect();
+        temps.sort_unstable_by(f64::total_cmp);
+        temps
+    });
+    let temp_percentile = |pct| temps.as_deref().and_then(|temps| nearest_rank(temps, pct));
+
+    let (powered_reports, powered_hashrate, total_power) = reports
+        .clone()
+        .filter_map(|report| Some((report.hashrate, report.power?)))
+        .fold(
+            (0usize, 0.0, 0.0),
+            |(n, h_acc, p_acc), (hashrate, power)| (n + 1, h_acc + hashrate, p_acc + power),
+        );
+    let avg_power = (powered_reports > 0)
+        .then(|| finite_or_zero(name, "avg_power", total_power / powered_reports as f64));
+    // Idle or misreporting rigs can claim zero watts; no efficiency rather than infinity.
+    let avg_efficiency = (total_power > 0.0)
+        .then(|| finite_or_zero(name, "avg_efficiency", powered_hashrate / total_power));
+
+    let (avg_report_interval_secs, max_report_interval_secs) = report_intervals(reports);
+
+    // Step 2: Compare against the previous tick's workers to get churn.
+    let workers_joined = unique_workers
+        .keys()
+        .filter(|worker| !previous_workers.contains(worker.as_str()))
+        .count();
+    let workers_left = previous_workers
+        .iter()
+        .filter(|worker| !unique_workers.contains_key(worker))
+        .count();
+
+    // Share of workers whose latest report is fresher than the window itself demands,
+    // against the server clock. Future-dated reports count as fresh.
+    let healthy_workers: u64 = unique_workers
+        .values()
+        .filter(|(latest, _, _)| latest.saturating_add(config.healthy_within_secs) >= now_ts)
+        .map(|&(_, _, workers)| workers)
+        .sum();
+    let healthy_worker_pct = if worker_count == 0 {
+        0.0
+    } else {
+        healthy_workers as f64 * 100.0 / worker_count as f64
+    };
+
+    // Step 3: Create the final stats struct for this pool.
+    let pool_stats = if report_count > 0 {
+        PoolStats {
+            workers: worker_count as usize,
+            avg_hashrate: if hashrate_reports > 0 {
+                finite_or_zero(
+                    name,
+                    "avg_hashrate",
+                    avg_hashrate_sum / hashrate_reports as f64,
+                )
+            } else {
+                0.0
+            },
+            avg_temp: if avg_reports > 0 {
+                round_to(
+                    finite_or_zero(name, "avg_temp", avg_temp_sum / avg_reports as f64),
+                    config.temp_precision,
+                )
+            } else {
+                0.0
+            },
+            sum_hashrate: finite_or_zero(name, "sum_hashrate", total_hashrate),
+            sum_temp: finite_or_zero(name, "sum_temp", total_temp),
+            // Needs every pool's total, so the data actor fills it in afterwards.
+            hashrate_share_pct: 0.0,
+            pool_hashrate: finite_or_zero(name, "pool_hashrate", pool_hashrate),
+            reports_per_sec: finite_or_zero(name, "reports_per_sec", reports_per_sec),
+            workers_joined,
+            workers_left,
+            healthy_worker_pct: Some(healthy_worker_pct),
+            zero_hashrate_workers: Some(zero_hashrate_workers),
+            oldest_report_age_secs: Some(oldest_report_age_secs),
+            max_temp,
+            // Set by the data actor, which knows the pool's thresholds and history.
+            temp_alerting: false,
+            hashrate_alerting: false,
+            stale: false,
+            interrupted: config
+                .interruption_gap_secs
+                .is_some_and(|gap| max_report_interval_secs > gap),
+            median_temp: temp_percentile(50.0),
+            p95_temp: temp_percentile(95.0),
+            p99_temp: temp_percentile(99.0),
+            avg_power,
+            avg_efficiency,
+            avg_report_interval_secs: Some(avg_report_interval_secs),
+            max_report_interval_secs: Some(max_report_interval_secs),
+            // Only known to the data actor, which knows when the reports arrived.
+            avg_processing_delay_secs: 0.0,
+        }
+    } else {
+        // If there are no reports, return a default state with 0 workers and 0.0 averages
+        // and nothing for the fields that describe the reports themselves.
+        PoolStats {
+            workers_left,
+            ..PoolStats::default()
+        }
+    };
+
+    *previous_workers = unique_workers.into_keys().cloned().collect();
+    pool_stats
+}
+
+/// `compute_pool_stats` for `--incremental-stats`: reads the running totals instead
+/// of the reports, so it costs O(workers) rather than O(reports). Fields that need
+/// the individual reports are left out.
+fn incremental_pool_stats(
+    name: &str,
+    totals: &RunningTotals,
+    reports_per_sec: f64,
+    previous_workers: &mut HashSet<String>,
+    config: &DataActorConfig,
+) -> PoolStats {
+    let workers_joined = totals
+        .workers
+        .keys()
+        .filter(|worker| !previous_workers.contains(worker.as_str()))
+        .count();
+    let workers_left = previous_workers
+        .iter()
+        .filter(|worker| !totals.workers.contains_key(worker.as_str()))
+        .count();
+
+    let pool_stats = if totals.reports > 0 {
+        let reports = totals.reports as f64;
+        PoolStats {
+            workers: totals
+                .workers
+                .values()
+                .map(|&(_, workers, _)| workers as usize)
+                .sum(),
+            pool_hashrate: finite_or_zero(
+                name,
+                "pool_hashrate",
+                totals
+                    .workers
+                    .values()
+                    .map(|&(_, workers, hashrate)| hashrate * workers as f64)
+                    .sum(),
+            ),
+            avg_hashrate: finite_or_zero(name, "avg_hashrate", totals.hashrate / reports),
+            avg_temp: round_to(
+                finite_or_zero(name, "avg_temp", totals.temp / reports),
+                config.temp_precision,
+            ),
+            sum_hashrate: finite_or_zero(name, "sum_hashrate", totals.hashrate),
+            sum_temp: finite_or_zero(name, "sum_temp", totals.temp),
+            reports_per_sec: finite_or_zero(name, "reports_per_sec", reports_per_sec),
+            workers_joined,
+            workers_left,
+            avg_power: (totals.powered_reports > 0).then(|| {
+                finite_or_zero(
+                    name,
+                    "avg_power",
+                    totals.power / totals.powered_reports as f64,
+                )
+            }),
+            avg_efficiency: (totals.power > 0.0).then(|| {
+                finite_or_zero(
+                    name,
+                    "avg_efficiency",
+                    totals.powered_hashrate / totals.power,
+                )
+            }),
+            ..PoolStats::default()
+        }
+    } else {
+        PoolStats {
+            workers_left,
+            ..PoolStats::default()
+        }
+    };
+
+    *previous_workers = totals.workers.keys().cloned().collect();
+    pool_stats
+}
+
+/// Each report's temperature as the median of its worker's last `window`
+/// temperatures up to and including it, ordered by timestamp and, within the same
+/// second, by arrival. Reports without `window - 1` before them use the worker's
+/// first `window` instead, and workers with fewer than 3 keep their raw readings,
+/// as a median of 1 or 2 can't outvote a glitch. Indexed like `reports`.
+fn median_filtered_temps<'a>(reports: impl Iterator<Item = &'a Report>, window: usize) -> Vec<f64> {
+    let reports: Vec<&Report> = reports.collect();
+    let mut by_worker: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, report) in reports.iter().enumerate() {
+        by_worker.entry(&report.worker_id).or_default().push(index);
+    }
+
+    let mut filtered: Vec<f64> = reports.iter().map(|report| report.temperature).collect();
+    let mut recent = Vec::with_capacity(window);
+    for mut indices in by_worker.into_values() {
+        if indices.len() < 3 {
+            continue;
+        }
+        // Stable, so reports of the same second stay in arrival order.
+        indices.sort_by_key(|&index| reports[index].timestamp);
+        for (position, &index) in indices.iter().enumerate() {
+            let last = position.max(window - 1).min(indices.len() - 1);
+            let first = (last + 1).saturating_sub(window);
+            recent.clear();
+            recent.extend(
+                indices[first..=last]
+                    .iter()
+                    .map(|&i| reports[i].temperature),
+            );
+            recent.sort_unstable_by(f64::total_cmp);
+            filtered[index] = median(&recent);
+        }
+    }
+    filtered
+}
+
+/// The middle value of sorted `values`, or the mean of the two middle ones.
+fn median(values: &[f64]) -> f64 {
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Mean and max gap between consecutive report timestamps. Reports mostly arrive in
+/// order, but client clocks and retries don't guarantee it, so they are sorted first.
+fn report_intervals<'a>(reports: impl Iterator<Item = &'a Report>) -> (f64, u64) {
+    let mut timestamps: Vec<u64> = reports.map(|report| report.timestamp).collect();
+    if timestamps.len() < 2 {
+        return (0.0, 0);
+    }
+    timestamps.sort_unstable();
+
+    let (sum, max) = timestamps
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .fold((0u64, 0u64), |(sum, max), gap| {
+            (sum.saturating_add(gap), max.max(gap))
+        });
+    (sum as f64 / (timestamps.len() - 1) as f64, max)
+}
+
+fn clamp_timestamp(timestamp: u64, now_ts: u64) -> u64 {
+    timestamp.min(now_ts.saturating_add(MAX_FUTURE_SKEW_SECS))
+}
+
+/// Rounds half away from zero to the given number of decimal places.
+fn round_to(value: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals.min(i32::MAX as u32) as i32);
+    let rounded = (value * factor).round() / factor;
+    // Past f64's precision the scaling itself overflows; keep the value as is then.
+    if rounded.is_finite() { rounded } else { value }
+}
+
+/// Nearest-rank percentile of an already sorted slice, `None` if it is empty.
+fn nearest_rank(sorted: &[f64], pct: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// `serde_json` writes NaN and infinities as `null`, which strict clients reject.
+/// Any such result (e.g. from a poisoned input) is published as 0.0 instead.
+fn finite_or_zero(name: &str, field: &str, value: f64) -> f64 {
+    if value.is_finite() {
+        value
+    } else {
+        warn!(name, field, value, "Non-finite stat replaced with 0.0");
+        0.0
+    }
+}
+
+/// Loads the stats cached by `stats_cache_writer` during the previous run, unless
+/// they were generated more than `max_age_secs` ago.
+async fn load_cached_stats(path: &FsPath, max_age_secs: u64) -> Option<PublishedStats> {
+    let json = match tokio::fs::read_to_string(path).await {
+        Ok(json) => json,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "Failed to read the stats cache");
+            return None;
+        }
+    };
+    match serde_json::from_str::<AllStats>(&json) {
+        Ok(stats) => {
+            let now_ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let age_secs = now_ts.saturating_sub(stats.generated_at);
+            if age_secs > max_age_secs {
+                info!(
+                    path = %path.display(),
+                    age_secs,
+                    discarded_pools = stats.pools.len(),
+                    "Ignoring a stats cache older than the window"
+                );
+                return None;
+            }
+            Some(PublishedStats { stats, json })
+        }
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "Ignoring an unreadable stats cache");
+            None
+        }
+    }
+}
+
+/// Writes every published snapshot to `path`. The file is replaced atomically, so a
+/// crash mid-write never leaves a truncated cache behind.
+async fn stats_cache_writer(mut stats_rx: watch::Receiver<Arc<PublishedStats>>, path: PathBuf) {
+    let tmp_path = path.with_extension("tmp");
+
+    while stats_rx.changed().await.is_ok() {
+        let json = stats_rx.borrow_and_update().json.clone();
+        let result = async {
+            tokio::fs::write(&tmp_path, json).await?;
+            tokio::fs::rename(&tmp_path, &path).await
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(path = %path.display(), error = %err, "Failed to write the stats cache");
+        }
+    }
+}
+
+/// Prints every published snapshot to stdout as a line of JSON. Stops if stdout is
+/// closed, e.g. because the reading end of the pipe went away.
+async fn stdout_emitter(mut stats_rx: watch::Receiver<Arc<PublishedStats>>) {
+    let mut stdout = tokio::io::stdout();
+
+    while stats_rx.changed().await.is_ok() {
+        let mut line = stats_rx.borrow_and_update().json.clone();
+        line.push('\n');
+        let result = async {
+            stdout.write_all(line.as_bytes()).await?;
+            stdout.flush().await
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(error = %err, "Failed to write stats to stdout, no longer emitting them");
+            break;
+        }
+    }
+}
+
+/// Reads `path` line by line and forwards each report like `POST /report` would.
+/// With `speed`, waits out the gaps between consecutive report timestamps, divided
+/// by `speed`; reports that go back in time are sent right away.
+async fn replay_reports(state: AppState, path: PathBuf, speed: Option<f64>) {
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            error!(path = %path.display(), error = %err, "Failed to open the replay file");
+            return;
+        }
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let mut line_number = 0u64;
+    let (mut replayed, mut skipped) = (0u64, 0u64);
+    let mut previous_timestamp = None;
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                error!(path = %path.display(), error = %err, "Failed to read the replay file");
+                break;
+            }
+        };
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let report = match serde_json::from_str::<Report>(&line) {
+            Ok(report) => report,
+            Err(err) => {
+                warn!(line = line_number, error = %err, "Skipping an unparseable replay line");
+                skipped += 1;
+                continue;
+            }
+        };
+        if let Err(error) = check_report_limits(&state, &report) {
+            warn!(
+                line = line_number,
+                error, "Skipping a replayed report over the limits"
+            );
+            skipped += 1;
+            continue;
+        }
+
+        if let (Some(speed), Some(previous)) = (speed, previous_timestamp) {
+            let gap_secs = report.timestamp.saturating_sub(previous) as f64 / speed;
+            if gap_secs.is_finite() && gap_secs > 0.0 {
+                tokio::time::sleep(Duration::from_secs_f64(gap_secs)).await;
+            }
+        }
+        previous_timestamp = Some(report.timestamp);
+
+        if forward_report(&state, report).await != StatusCode::OK {
+            break;
+        }
+        replayed += 1;
+    }
+
+    info!(path = %path.display(), replayed, skipped, "Replay finished");
+}
+
+#[cfg(feature = "sqlite")]
+fn open_stats_db(path: &FsPath) -> rusqlite::Result<rusqlite::Connection> {
+    let connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS pool_stats (
+            generated_at INTEGER NOT NULL,
+            pool TEXT NOT NULL,
+            workers INTEGER NOT NULL,
+            avg_hashrate REAL NOT NULL,
+            avg_temp REAL NOT NULL,
+            sum_hashrate REAL NOT NULL,
+            reports_per_sec REAL NOT NULL,
+            -- The complete PoolStats as JSON, for the fields without a column.
+            stats TEXT NOT NULL,
+            PRIMARY KEY (generated_at, pool)
+        )",
+    )?;
+    Ok(connection)
+}
+
+/// Inserts one row per pool, all in a single transaction so a tick costs one sync.
+#[cfg(feature = "sqlite")]
+fn insert_pool_stats(
+    connection: &mut rusqlite::Connection,
+    stats: &AllStats,
+) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare_cached(
+            "INSERT OR REPLACE INTO pool_stats
+                (generated_at, pool, workers, avg_hashrate, avg_temp, sum_hashrate, reports_per_sec, stats)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for (pool, pool_stats) in &stats.pools {
+            let json = serde_json::to_string(pool_stats)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+            insert.execute(rusqlite::params![
+                stats.generated_at as i64,
+                pool,
+                pool_stats.workers as i64,
+                pool_stats.avg_hashrate,
+                pool_stats.avg_temp,
+                pool_stats.sum_hashrate,
+                pool_stats.reports_per_sec,
+                json,
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// Persists every published snapshot to SQLite next to the in-memory serving.
+/// rusqlite is blocking, so the connection is handed to a blocking task per write.
+#[cfg(feature = "sqlite")]
+async fn sqlite_writer(mut stats_rx: watch::Receiver<Arc<PublishedStats>>, path: PathBuf) {
+    let open_path = path.clone();
+    let mut connection = match tokio::task::spawn_blocking(move || open_stats_db(&open_path)).await
+    {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(err)) => {
+            error!(path = %path.display(), error = %err, "Failed to open the SQLite database, stats won't be persisted");
+            return;
+        }
+        Err(err) => {
+            error!(error = %err, "Opening the SQLite database panicked, stats won't be persisted");
+            return;
+        }
+    };
+
+    while stats_rx.changed().await.is_ok() {
+        let snapshot = stats_rx.borrow_and_update().clone();
+        let write = tokio::task::spawn_blocking(move || {
+            let result = insert_pool_stats(&mut connection, &snapshot.stats);
+            (connection, result)
+        });
+        match write.await {
+            Ok((returned, result)) => {
+                connection = returned;
+                if let Err(err) = result {
+                    warn!(path = %path.display(), error = %err, "Failed to persist stats to SQLite");
+                }
+            }
+            Err(err) => {
+                error!(error = %err, "SQLite write panicked, stats are no longer persisted");
+                return;
+            }
+        }
+    }
+}
+
+/// The gRPC API of `proto/miner_reports.proto`. Reports take the same path as
+/// the REST ones, so limits, normalization and `--ack-mode` apply unchanged.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::{
+        AllStats, AppState, AuthTokens, PoolStats, Report, Scope, check_report_limits,
+        forward_report, has_scope,
+    };
+    use axum::http::StatusCode;
+    use std::sync::Arc;
+    use tonic::{Request, Response, Status, Streaming};
+
+    mod proto {
+        tonic::include_proto!("miner_reports");
+    }
+
+    pub use proto::miner_reports_server::MinerReportsServer;
+
+    pub struct MinerReportsService {
+        state: AppState,
+        auth_tokens: Option<Arc<AuthTokens>>,
+    }
+
+    impl MinerReportsService {
+        pub fn new(state: AppState, auth_tokens: Option<Arc<AuthTokens>>) -> Self {
+            Self { state, auth_tokens }
+        }
+
+        /// The gRPC side of `require_scope`, reading the `authorization` metadata.
+        // `Status` is large, but it is what every handler returns anyway.
+        #[allow(clippy::result_large_err)]
+        fn authorize<T>(&self, request: &Request<T>, scope: Scope) -> Result<(), Status> {
+            let Some(tokens) = &self.auth_tokens else {
+                return Ok(());
+            };
+            let authorization = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+            if !has_scope(tokens, authorization, scope) {
+                return Err(Status::permission_denied("token lacks the required scope"));
+            }
+            Ok(())
+        }
+    }
+
+    impl From<proto::Report> for Report {
+        fn from(report: proto::Report) -> Self {
+            Self {
+                worker_id: report.worker_id,
+                pool: report.pool,
+                hashrate: report.hashrate,
+                temperature: report.temperature,
+                timestamp: report.timestamp,
+                ttl_secs: report.ttl_secs,
+                power: report.power,
+                region: report.region,
+                received_at: None,
+                batch: None,
+            }
+        }
+    }
+
+    impl From<&PoolStats> for proto::PoolStats {
+        fn from(stats: &PoolStats) -> Self {
+            Self {
+                workers: stats.workers as u64,
+                avg_hashrate: stats.avg_hashrate,
+                avg_temp: stats.avg_temp,
+                sum_hashrate: stats.sum_hashrate,
+                sum_temp: stats.sum_temp,
+                hashrate_share_pct: stats.hashrate_share_pct,
+                pool_hashrate: stats.pool_hashrate,
+                reports_per_sec: stats.reports_per_sec,
+                workers_joined: stats.workers_joined as u64,
+                workers_left: stats.workers_left as u64,
+                healthy_worker_pct: stats.healthy_worker_pct,
+                zero_hashrate_workers: stats.zero_hashrate_workers.map(|workers| workers as u64),
+                oldest_report_age_secs: stats.oldest_report_age_secs,
+                max_temp: stats.max_temp,
+                temp_alerting: stats.temp_alerting,
+                hashrate_alerting: stats.hashrate_alerting,
+                stale: stats.stale,
+                interrupted: stats.interrupted,
+                median_temp: stats.median_temp,
+                p95_temp: stats.p95_temp,
+                p99_temp: stats.p99_temp,
+                avg_power: stats.avg_power,
+                avg_efficiency: stats.avg_efficiency,
+                avg_report_interval_secs: stats.avg_report_interval_secs,
+                max_report_interval_secs: stats.max_report_interval_secs,
+                avg_processing_delay_secs: stats.avg_processing_delay_secs,
+            }
+        }
+    }
+
+    impl From<&AllStats> for proto::AllStats {
+        fn from(stats: &AllStats) -> Self {
+            Self {
+                pools: stats
+                    .pools
+                    .iter()
+                    .map(|(pool, pool_stats)| (pool.clone(), pool_stats.into()))
+                    .collect(),
+                total: Some(proto::TotalStats {
+                    reports_per_sec: stats.total.reports_per_sec,
+                    workers: stats.total.workers as u64,
+                    pool_workers: stats.total.pool_workers as u64,
+                }),
+                generated_at: stats.generated_at,
+            }
+        }
+    }
+
+    async fn submit(state: &AppState, report: proto::Report) -> Result<(), Status> {
+        let report = Report::from(report);
+        check_report_limits(state, &report).map_err(Status::invalid_argument)?;
+        match forward_report(state, report).await {
+            StatusCode::OK => Ok(()),
+            StatusCode::SERVICE_UNAVAILABLE => Err(Status::unavailable(
+                "report was queued but not processed in time",
+            )),
+            _ => Err(Status::internal("internal error")),
+        }
+    }
+
+    #[tonic::async_trait]
+    impl proto::miner_reports_server::MinerReports for MinerReportsService {
+        async fn submit_report(
+            &self,
+            request: Request<proto::Report>,
+        ) -> Result<Response<proto::SubmitReply>, Status> {
+            self.authorize(&request, Scope::Write)?;
+            submit(&self.state, request.into_inner()).await?;
+            Ok(Response::new(proto::SubmitReply { accepted: 1 }))
+        }
+
+        /// Unlike `POST /reports`, a stream can't be checked up front, so the reports
+        /// before a rejected one stay accepted; the error says how many there were.
+        async fn submit_reports(
+            &self,
+            request: Request<Streaming<proto::Report>>,
+        ) -> Result<Response<proto::SubmitReply>, Status> {
+            self.authorize(&request, Scope::Write)?;
+            let mut reports = request.into_inner();
+            let mut accepted = 0;
+            while let Some(report) = reports.message().await? {
+                submit(&self.state, report).await.map_err(|status| {
+                    let message = format!("{} ({accepted} accepted before)", status.message());
+                    Status::new(status.code(), message)
+                })?;
+                accepted += 1;
+            }
+            Ok(Response::new(proto::SubmitReply { accepted }))
+        }
+
+        async fn get_stats(
+            &self,
+            request: Request<proto::GetStatsRequest>,
+        ) -> Result<Response<proto::AllStats>, Status> {
+            self.authorize(&request, Scope::Read)?;
+            let stats = proto::AllStats::from(&self.state.stats_rx.borrow().stats);
+            Ok(Response::new(stats))
+        }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+mod mqtt {
+    use super::{AppState, Report, check_report_limits, forward_report};
+    use axum::http::StatusCode;
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+    use std::future::Future;
+    use std::time::Duration;
+    use tracing::{info, warn};
+
+    const DEFAULT_PORT: u16 = 1883;
+    const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+    pub fn parse_broker(value: &str) -> Result<(String, u16), String> {
+        let (host, port) = match value.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("`{port}` is not a valid port"))?,
+            ),
+            None => (value, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err("broker host is empty".to_string());
+        }
+        Ok((host.to_string(), port))
+    }
+
+    /// The topic level matched by the first `+` or `#` wildcard of `filter`.
+    fn pool_from_topic<'a>(filter: &str, topic: &'a str) -> Option<&'a str> {
+        filter
+            .split('/')
+            .zip(topic.split('/'))
+            .find(|(filter_level, _)| matches!(*filter_level, "+" | "#"))
+            .map(|(_, topic_level)| topic_level)
+            .filter(|level| !level.is_empty())
+    }
+
+    /// Subscribes to `topic` and forwards every report published on it until
+    /// `shutdown` resolves. A lost broker connection is retried indefinitely and
+    /// doesn't affect the other ingest paths.
+    pub async fn run(
+        state: AppState,
+        (host, port): (String, u16),
+        topic: String,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        let client_id = format!("miner-reports-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut event_loop) = AsyncClient::new(options, 16);
+        tokio::pin!(shutdown);
+
+        loop {
+            let event = tokio::select! {
+                _ = &mut shutdown => break,
+                event = event_loop.poll() => event,
+            };
+            match event {
+                // The session is clean, so every (re)connect needs a fresh subscription.
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!(topic, "Connected to the MQTT broker");
+                    if let Err(err) = client.try_subscribe(&topic, QoS::AtLeastOnce) {
+                        warn!(topic, error = %err, "Failed to subscribe to the MQTT topic");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let mut report = match serde_json::from_slice::<Report>(&publish.payload) {
+                        Ok(report) => report,
+                        Err(err) => {
+                            warn!(topic = publish.topic, error = %err, "Skipping an unparseable MQTT report");
+                            continue;
+                        }
+                    };
+                    if report.pool.is_none() {
+                        report.pool = pool_from_topic(&topic, &publish.topic).map(str::to_string);
+                    }
+                    if let Err(error) = check_report_limits(&state, &report) {
+                        warn!(
+                            topic = publish.topic,
+                            error, "Skipping an MQTT report over the limits"
+                        );
+                        continue;
+                    }
+                    if forward_report(&state, report).await == StatusCode::INTERNAL_SERVER_ERROR {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!(error = %err, "MQTT connection failed, retrying");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+
+        client.try_disconnect().ok();
+    }
+}
+
+/// Files a report under its pool, counts it for the next tick's rates and tells a
+/// waiting `--ack-mode processed` sender that it is in.
+fn ingest_report(
+    pools_data: &mut HashMap<String, PoolData>,
+    region_reports_since_tick: &mut HashMap<String, u64>,
+    config: &DataActorConfig,
+    queued: QueuedReport,
+) {
+    let QueuedReport {
+        report,
+        processed_tx,
+    } = queued;
+    *region_reports_since_tick
+        .entry(report.region().to_string())
+        .or_default() += report.weight();
+    let pool_data = pools_data.entry(report.pool().to_string()).or_default();
+    pool_data.reports_since_tick += report.weight();
+    pool_data.received_since_tick.add(&report);
+    pool_data.reports_received += report.weight();
+    pool_data.last_report_at = Instant::now();
+    pool_data.newest_timestamp = pool_data.newest_timestamp.max(report.timestamp);
+    if config.incremental_stats {
+        pool_data.totals.add(&report);
+    }
+    if config.percentiles && config.percentile_mode == PercentileMode::Tdigest {
+        pool_data.temp_digests.add(&report);
+    }
+    if config.incremental_stats {
+        // Kept in expiry order so the tick prunes from the front instead of scanning
+        // every report. Most reports expire after the ones before them, so this is
+        // usually an append.
+        let expires_at = report.expires_at(config);
+        let position = pool_data
+            .reports
+            .partition_point(|queued| queued.expires_at(config) <= expires_at);
+        pool_data.reports.insert(position, report);
+    } else {
+        pool_data.reports.push_back(report);
+    }
+    if let Some(processed_tx) = processed_tx {
+        processed_tx.send(()).ok();
+    }
+}
+
+async fn data_actor(
+    mut report_rx: mpsc::Receiver<QueuedReport>,
+    mut command_rx: mpsc::Receiver<DataActorCommand>,
+    stats_tx: watch::Sender<Arc<PublishedStats>>,
+    mut shutdown_rx: watch::Receiver<bool>,
+    config: DataActorConfig,
+) {
+    let mut pools_data: HashMap<String, PoolData> = HashMap::new();
+    let mut calculation_interval = tokio::time::interval(RECALC_INTERVAL);
+    // Recalculations go first, so catching up on missed ticks in a burst would keep
+    // reports waiting. The rates use the real elapsed time anyway.
+    calculation_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_tick = Instant::now();
+    let mut holding_initial_snapshot = config.hold_initial_snapshot;
+    // Start of the tumbling window reports are currently collected into. The window
+    // in progress at startup is published partial, once it ends.
+    let mut current_window_start = tumbling_window_start(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        &config,
+    );
+    let mut ticks_since_prune = 0;
+    let mut ticks_since_resync = 0;
+    // `AllStats::content_hash` of the last published snapshot, for `--publish-on-change`.
+    let mut last_content_hash = None;
+    // The last published snapshot, for `--publish-smoothing`. None after a reset.
+    let mut last_published: Option<Arc<PublishedStats>> = None;
+    // Per-region counterparts of `PoolData::reports_since_tick` and `previous_workers`.
+    let mut region_reports_since_tick: HashMap<String, u64> = HashMap::new();
+    let mut previous_region_workers: HashMap<String, HashSet<String>> = HashMap::new();
+
+    // The branches are polled in order, so a due recalculation always runs before
+    // more reports are ingested, and reports are ingested in bounded batches.
+    // Without that, a flood of reports could hold back the published stats.
+    loop {
+        tokio::select! {
+            biased;
+
+            // Branch 1: An admin command is received.
+            Some(command) = command_rx.recv() => {
+                match command {
+                    DataActorCommand::SetPoolEnabled { pool, enabled, reply_tx } => {
+                        let found = match pools_data.get_mut(&pool) {
+                            Some(pool_data) => {
+                                pool_data.enabled = enabled;
+                                info!(pool = %pool, enabled, "Pool visibility changed");
+                                true
+                            }
+                            None => false,
+                        };
+                        reply_tx.send(found).ok();
+                    }
+                    DataActorCommand::HashrateHistogram { pool, edges, reply_tx } => {
+                        let histogram = pools_data.get(&pool).map(|pool_data| {
+                            let hashrates = pool_data.reports.iter().map(|report| report.hashrate);
+                            Histogram::new(edges, hashrates)
+                        });
+                        reply_tx.send(histogram).ok();
+                    }
+                    DataActorCommand::WorkerStats { worker_id, reply_tx } => {
+                        // Worker IDs aren't indexed, so this walks every retained report.
+                        let pools = pools_data
+                            .iter()
+                            .filter(|(_, pool_data)| pool_data.enabled)
+                            .filter_map(|(pool_name, pool_data)| {
+                                let latest = pool_data
+                                    .reports
+                                    .iter()
+                                    .filter(|report| report.worker_id == worker_id)
+                                    .max_by_key(|report| report.timestamp)?;
+                                let stats = WorkerPoolStats {
+                                    hashrate: latest.hashrate,
+                                    temperature: latest.temperature,
+                                    timestamp: latest.timestamp,
+                                };
+                                Some((pool_name.clone(), stats))
+                            })
+                            .collect();
+                        reply_tx.send(pools).ok();
+                    }
+                    DataActorCommand::Reset { reply_tx } => {
+                        // Reports queued before the reset was handled go too, so none
+                        // sent before it resurface in the next snapshot.
+                        while let Ok(queued) = report_rx.try_recv() {
+                            ingest_report(
+                                &mut pools_data,
+                                &mut region_reports_since_tick,
+                                &config,
+                                queued,
+                            );
+                        }
+                        let discarded_reports: usize =
+                            pools_data.values().map(|pool_data| pool_data.reports.len()).sum();
+                        for (pool_name, pool_data) in &mut pools_data {
+                            pool_data.alerts.clear(pool_name);
+                        }
+                        // An admin's choice to hide a pool outlives its data.
+                        pools_data.retain(|_, pool_data| !pool_data.enabled);
+                        for pool_data in pools_data.values_mut() {
+                            *pool_data = PoolData {
+                                enabled: false,
+                                ..PoolData::default()
+                            };
+                        }
+                        region_reports_since_tick.clear();
+                        previous_region_workers.clear();
+                        last_published = None;
+                        info!(discarded_reports, "Stats reset");
+                        reply_tx.send(()).ok();
+                    }
+                }
+            }
+
+            // Branch 2: The 1-second timer ticks, triggering a stats recalculation.
+            _ = calculation_interval.tick() => {
+                if holding_initial_snapshot {
+                    continue;
+                }
+
+                let now_ts = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+
+                // A tumbling window only publishes once it has ended. Until then the
+                // per-tick counters keep accumulating, so the rates below come out as
+                // averages over the whole window. Reports that already belong to the
+                // next window are set aside until this one is published.
+                let mut next_window_reports = HashMap::new();
+                if config.window_mode == WindowMode::Tumbling {
+                    let window_start = tumbling_window_start(now_ts, &config);
+                    if window_start == current_window_start {
+                        continue;
+                    }
+                    current_window_start = window_start;
+                    for (pool_name, pool_data) in &mut pools_data {
+                        let carried = pool_data.split_off_next_window(window_start, &config);
+                        for report in &carried {
+                            if let Some(count) =
+                                region_reports_since_tick.get_mut(report.region())
+                            {
+                                *count = count.saturating_sub(report.weight());
+                            }
+                        }
+                        next_window_reports.insert(pool_name.clone(), carried);
+                    }
+                }
+
+                // The interval may fire late under load, so rates use the real elapsed time.
+                let elapsed_secs = last_tick.elapsed().as_secs_f64();
+                last_tick = Instant::now();
+                let per_sec = |count: u64| {
+                    if elapsed_secs > 0.0 { count as f64 / elapsed_secs } else { 0.0 }
+                };
+                let mut total_reports_since_tick = 0;
+
+                ticks_since_prune += 1;
+                let prune_due = ticks_since_prune >= config.prune_interval_ticks;
+                if prune_due {
+                    ticks_since_prune = 0;
+                }
+
+                ticks_since_resync += 1;
+                let resync_totals = config.incremental_stats
+                    && ticks_since_resync >= config.incremental_resync_ticks;
+                if resync_totals {
+                    ticks_since_resync = 0;
+                }
+
+                let mut pools = pools_data.iter_mut()
+                    .filter_map(|(pool_name, pool_data)| {
+                        let reports_per_sec = per_sec(pool_data.reports_since_tick);
+                        let received_since_tick = std::mem::take(&mut pool_data.received_since_tick);
+                        total_reports_since_tick += pool_data.reports_since_tick;
+                        pool_data.reports_since_tick = 0;
+
+                        // Step 1: Prune expired reports when due. Reports may carry their own
+                        // TTL, so the deque is only ordered by expiry under --incremental-stats;
+                        // otherwise it has to be scanned in full.
+                        let reference_ts = match config.expire_relative_to {
+                            ExpireRelativeTo::Server => now_ts,
+                            ExpireRelativeTo::Newest => pool_data.newest_timestamp,
+                        };
+                        // A tumbling window holds exactly the reports received during it.
+                        if config.window_mode == WindowMode::Sliding && prune_due {
+                            if config.incremental_stats {
+                                while let Some(report) = pool_data.reports.front()
+                                    && report.expires_at(&config) < reference_ts
+                                {
+                                    pool_data.totals.remove(report);
+                                    pool_data.reports.pop_front();
+                                }
+                            } else {
+                                pool_data.reports.retain(|report| {
+                                    report.expires_at(&config) >= reference_ts
+                                });
+                            }
+                        }
+                        if resync_totals {
+                            pool_data.totals = PoolTotals::from_reports(pool_data.reports.iter());
+                        }
+                        let tdigest_percentiles = config.percentiles
+                            && config.percentile_mode == PercentileMode::Tdigest;
+                        if tdigest_percentiles {
+                            let expiry_reference = (config.window_mode == WindowMode::Sliding)
+                                .then_some(reference_ts);
+                            pool_data.temp_digests.update(&config, expiry_reference);
+                        }
+
+                        // Disabled and not yet established pools are still pruned above,
+                        // but hidden from the output.
+                        if !pool_data.enabled
+                            || pool_data.reports_received < config.min_reports_to_appear
+                        {
+                            return None;
+                        }
+
+                        // Step 2: Calculate the stats for the remaining reports.
+                        let mut pool_stats = if config.incremental_stats {
+                            incremental_pool_stats(
+                                pool_name,
+                                &pool_data.totals.pool,
+                                reports_per_sec,
+                                &mut pool_data.previous_workers,
+                                &config,
+                            )
+                        } else {
+                            compute_pool_stats(
+                                pool_name,
+                                pool_data.reports.iter(),
+                                reports_per_sec,
+                                now_ts,
+                                &mut pool_data.previous_workers,
+                                &config,
+                            )
+                        };
+
+                        if tdigest_percentiles
+                            && let Some((p50, p95, p99)) = pool_data.temp_digests.quantiles()
+                        {
+                            pool_stats.median_temp = Some(p50);
+                            pool_stats.p95_temp = Some(p95);
+                            pool_stats.p99_temp = Some(p99);
+                        }
+
+                        pool_stats.avg_processing_delay_secs =
+                            received_since_tick.avg_delay_secs(last_tick);
+
+                        // Computed regardless, so churn stays correct while the pool is hidden.
+                        if let Some(retain_pool) = config.retain_pool
+                            && pool_data.reports.is_empty()
+                        {
+                            if pool_data.last_report_at.elapsed() > retain_pool {
+                                return None;
+                            }
+                            if let Some(last_stats) = &pool_data.last_stats {
+                                pool_stats = PoolStats {
+                                    stale: true,
+                                    reports_per_sec: 0.0,
+                                    hashrate_share_pct: 0.0,
+                                    avg_processing_delay_secs: 0.0,
+                                    ..last_stats.clone()
+                                };
+                            }
+                        }
+
+                        if config.retain_pool.is_none()
+                            && pool_data.reports.is_empty()
+                            && !config.show_empty_pools
+                        {
+                            return None;
+                        }
+
+                        Some((pool_name.clone(), pool_stats))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+
+                // Each pool's hashrate share is relative to the whole deployment, so it
+                // can only be worked out once every pool has been calculated.
+                let total_hashrate = live_hashrate(pools.values());
+                if total_hashrate > 0.0 {
+                    for pool_stats in pools.values_mut().filter(|stats| !stats.stale) {
+                        pool_stats.hashrate_share_pct =
+                            pool_stats.pool_hashrate * 100.0 / total_hashrate;
+                    }
+                }
+
+                // Stale pools keep their last stats exactly as they were.
+                if let Some(weight) = config.publish_smoothing
+                    && let Some(last_published) = &last_published
+                {
+                    for (pool_name, pool_stats) in &mut pools {
+                        if let Some(previous) = last_published.stats.pools.get(pool_name)
+                            && !pool_stats.stale
+                        {
+                            pool_stats.smooth(previous, weight, config.temp_precision);
+                        }
+                    }
+                }
+
+                // Alerts are evaluated against the smoothed stats, so they agree with
+                // what is published. A stale pool is published with the alert flags of
+                // its last stats, so its alerts stay as they were rather than being
+                // evaluated, and logged, against the empty stats.
+                for (pool_name, pool_stats) in pools.iter_mut().filter(|(_, stats)| !stats.stale) {
+                    let Some(pool_data) = pools_data.get_mut(pool_name) else {
+                        continue;
+                    };
+                    pool_data.alerts.evaluate(pool_name, pool_stats, &config);
+                    if config.retain_pool.is_some() && !pool_data.reports.is_empty() {
+                        pool_data.last_stats = Some(pool_stats.clone());
+                    }
+                }
+
+                // Step 3: Calculate the same stats per region over the published
+                // pools. The deployment-wide worker count comes along. With
+                // `--incremental-stats` both come from the pools' running totals, so
+                // this costs O(workers) rather than O(reports) too.
+                let published_pools = pools_data
+                    .iter()
+                    .filter(|(pool_name, _)| pools.contains_key(*pool_name))
+                    .map(|(_, pool_data)| pool_data);
+                // Worker id -> workers it stands for, as of its last received report.
+                let mut unique_workers: HashMap<&str, u64> = HashMap::new();
+                let region_reports_per_sec = |region: &str| {
+                    per_sec(region_reports_since_tick.get(region).copied().unwrap_or(0))
+                };
+                let regions = if config.incremental_stats {
+                    let mut totals_by_region: HashMap<&str, RunningTotals> = HashMap::new();
+                    for pool_data in published_pools {
+                        for (worker, &(_, workers, _)) in &pool_data.totals.pool.workers {
+                            unique_workers.insert(worker, workers);
+                        }
+                        for (region, totals) in &pool_data.totals.regions {
+                            totals_by_region.entry(region).or_default().merge(totals);
+                        }
+                    }
+                    totals_by_region
+                        .into_iter()
+                        .map(|(region, totals)| {
+                            let region_stats = incremental_pool_stats(
+                                region,
+                                &totals,
+                                region_reports_per_sec(region),
+                                previous_region_workers.entry(region.to_string()).or_default(),
+                                &config,
+                            );
+                            (region.to_string(), region_stats)
+                        })
+                        .collect::<BTreeMap<_, _>>()
+                } else {
+                    let mut reports_by_region: HashMap<&str, Vec<&Report>> = HashMap::new();
+                    for pool_data in published_pools {
+                        for report in &pool_data.reports {
+                            reports_by_region.entry(report.region()).or_default().push(report);
+                            unique_workers.insert(&report.worker_id, report.workers());
+                        }
+                    }
+                    reports_by_region
+                        .into_iter()
+                        .map(|(region, reports)| {
+                            let region_stats = compute_pool_stats(
+                                region,
+                                reports.iter().copied(),
+                                region_reports_per_sec(region),
+                                now_ts,
+                                previous_region_workers.entry(region.to_string()).or_default(),
+                                &config,
+                            );
+                            (region.to_string(), region_stats)
+                        })
+                        .collect::<BTreeMap<_, _>>()
+                };
+                region_reports_since_tick.clear();
+                previous_region_workers.retain(|region, _| regions.contains_key(region));
+
+                // Step 4: Assemble the final stats object and publish it.
+                let pool_workers = pools
+                    .values()
+                    .filter(|stats| !stats.stale)
+                    .map(|stats| stats.workers)
+                    .sum();
+                let current_stats = AllStats {
+                    pools,
+                    total: TotalStats {
+                        reports_per_sec: per_sec(total_reports_since_tick),
+                        workers: unique_workers.values().sum::<u64>() as usize,
+                        pool_workers,
+                    },
+                    generated_at: now_ts,
+                    regions,
+                    ..AllStats::default()
+                };
+
+                // With `--publish-on-change`, an unchanged snapshot isn't sent at all,
+                // so subscribers aren't woken up for nothing.
+                let unchanged = config.publish_on_change && {
+                    let content_hash = current_stats.content_hash();
+                    let unchanged = last_content_hash == Some(content_hash);
+                    last_content_hash = Some(content_hash);
+                    unchanged
+                };
+                if unchanged {
+                    debug!("Stats unchanged, skipping publish");
+                } else if let Ok(published) = PublishedStats::new(
+                    current_stats,
+                    config.output_profile,
+                    config.max_pools_in_response,
+                ) {
+                    info!(stats = %published.json, "Publishing new stats");
+                    let published = Arc::new(published);
+                    if config.publish_smoothing.is_some() {
+                        last_published = Some(published.clone());
+                    }
+                    // Send the new stats to all subscribed `get_stats` handlers.
+                    stats_tx.send(published).ok();
+                }
+
+                // Step 5: The window just published is complete; start the next one
+                // with just the reports set aside for it.
+                if config.window_mode == WindowMode::Tumbling {
+                    for (pool_name, pool_data) in &mut pools_data {
+                        let carried = next_window_reports.remove(pool_name).unwrap_or_default();
+                        for report in &carried {
+                            *region_reports_since_tick
+                                .entry(report.region().to_string())
+                                .or_default() += report.weight();
+                        }
+                        pool_data.start_next_window(carried, &config);
+                    }
+                }
+            }
+
+            // Branch 3: The server stopped accepting reports. Ingest whatever is still
+            // queued and stop, logging enough to tell that nothing was lost.
+            _ = shutdown_rx.changed() => {
+                let drain_started = Instant::now();
+                let pending_reports = report_rx.len();
+                while let Ok(queued) = report_rx.try_recv() {
+                    ingest_report(
+                        &mut pools_data,
+                        &mut region_reports_since_tick,
+                        &config,
+                        queued,
+                    );
+                }
+                let active_pools = pools_data
+                    .values()
+                    .filter(|pool_data| !pool_data.reports.is_empty())
+                    .count();
+                let retained_reports: usize =
+                    pools_data.values().map(|pool_data| pool_data.reports.len()).sum();
+                info!(
+                    pending_reports,
+                    active_pools,
+                    retained_reports,
+                    drain_ms = drain_started.elapsed().as_millis() as u64,
+                    "Report queue drained. Data actor shutting down."
+                );
+                break;
+            }
+
+            // Branch 4: New reports are received from the web handlers. Takes the
+            // ones already queued behind the first, up to the batch limit.
+            Some(queued) = report_rx.recv() => {
+                ingest_report(
+                    &mut pools_data,
+                    &mut region_reports_since_tick,
+                    &config,
+                    queued,
+                );
+                for _ in 1..config.max_reports_per_iteration.get() {
+                    let Ok(queued) = report_rx.try_recv() else {
+                        break;
+                    };
+                    ingest_report(
+                        &mut pools_data,
+                        &mut region_reports_since_tick,
+                        &config,
+                        queued,
+                    );
+                }
+                holding_initial_snapshot = false;
+            }
+
+            // Branch 5: All channels have closed, so the actor should shut down.
+            else => {
+                info!("Report channel closed. Data actor shutting down.");
+                break;
+            }
+        }
+    }
+}
+
+/// Back-off after a failed `accept`, which usually means the process is out of file
+/// descriptors; retrying immediately would just spin.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serves `app` like `axum::serve`, which can neither cap connections nor time out
+/// slow request headers. Connections over `max_connections` are closed right after
+/// accept instead of piling up, and clients that dribble their headers in for longer
+/// than `header_read_timeout` are disconnected. Returns once `shutdown` resolves and
+/// every open connection has finished.
+///
+/// Each binary in `src/bin` is a self-contained take on the same server and there
+/// is no library crate, so this has a copy in every server binary; change them
+/// together.
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    max_connections: NonZeroUsize,
+    header_read_timeout: Duration,
+    shutdown: impl Future<Output = ()>,
+) {
+    let permits = Arc::new(Semaphore::new(max_connections.get()));
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            // Dropping the stream closes it, so the client finds out right away.
+            debug!(%remote_addr, "Connection limit reached, rejecting connection");
+            continue;
+        };
+
+        let connection = builder
+            .serve_connection_with_upgrades(
+                TokioIo::new(stream),
+                TowerToHyperService::new(app.clone()),
+            )
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!(%remote_addr, error = %err, "Connection closed with an error");
+            }
+            drop(permit);
+        });
+    }
+
+    graceful.shutdown().await;
+}
+
+/// Waits for the data actor and reports it as dead if it stops, panics included,
+/// before it was asked to. Only its result is passed on; nothing restarts it, as
+/// the report queue went down with it.
+async fn supervise_data_actor(
+    data_actor: tokio::task::JoinHandle<()>,
+    shutdown_rx: watch::Receiver<bool>,
+    alive: Arc<AtomicBool>,
+) -> Result<(), tokio::task::JoinError> {
+    let result = data_actor.await;
+    if result.is_err() || !*shutdown_rx.borrow() {
+        alive.store(false, Ordering::Relaxed);
+        match &result {
+            Err(err) => error!(error = %err, "Data actor died, stats are no longer updated"),
+            Ok(()) => error!("Data actor stopped unexpectedly, stats are no longer updated"),
+        }
+    }
+    result
+}
+
+/// Resolves on Ctrl+C, on Unix SIGTERM, or once `POST /admin/shutdown` notifies
+/// `requested`.
+async fn shutdown_signal(requested: Arc<Notify>) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!(error = %err, "Failed to install SIGTERM handler");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+        _ = requested.notified() => {}
+    }
+    info!("Shutdown signal received");
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+
+    // The access log is emitted by tower_http at DEBUG, so only that target is
+    // raised; everything else stays at INFO to keep /report and /stats quiet.
+    let filter = if cli.access_log {
+        "info,tower_http=debug"
+    } else {
+        "info"
+    };
+    let subscriber = FmtSubscriber::builder().with_env_filter(EnvFilter::new(filter));
+    if cli.emit_stdout {
+        tracing::subscriber::set_global_default(subscriber.with_writer(std::io::stderr).finish())?;
+    } else {
+        tracing::subscriber::set_global_default(subscriber.finish())?;
+    }
+
+    info!(config = ?cli, "Service starting with configuration");
+
+    if cli.expiration_secs > cli.max_expiration_secs {
+        warn!(
+            expiration_secs = cli.expiration_secs,
+            max_expiration_secs = cli.max_expiration_secs,
+            "--expiration-secs is above --max-expiration-secs, clamping it"
+        );
+        cli.expiration_secs = cli.max_expiration_secs;
+    }
+    // Checked before anything is spawned, so a bad flag exits without having started
+    // the writers or the data actor.
+    if cli
+        .replay_speed
+        .is_some_and(|speed| !(speed.is_finite() && speed > 0.0))
+    {
+        bail!("--replay-speed must be a positive number");
+    }
+    if cli
+        .avg_window_secs
+        .is_some_and(|avg_window_secs| avg_window_secs > cli.expiration_secs)
+    {
+        bail!("--avg-window-secs must not exceed --expiration-secs");
+    }
+
+    let auth_tokens = match &cli.auth_file {
+        Some(path) => {
+            let tokens = load_auth_file(path)?;
+            info!(tokens = tokens.len(), "Loaded --auth-file");
+            Some(Arc::new(tokens))
+        }
+        None => None,
+    };
+
+    let (report_tx, report_rx) = mpsc::channel::<QueuedReport>(1024);
+    let (command_tx, command_rx) = mpsc::channel::<DataActorCommand>(16);
+    let initial_stats = match &cli.stats_cache_path {
+        Some(path) => load_cached_stats(
+            path,
+            cli.max_stats_cache_age_secs.unwrap_or(cli.expiration_secs),
+        )
+        .await
+        .inspect(|cached| {
+            info!(
+                path = %path.display(),
+                generated_at = cached.stats.generated_at,
+                "Serving cached stats until the first fresh snapshot"
+            );
+        }),
+        None => None,
+    };
+    let initial_stats_cached = initial_stats.is_some();
+    let (stats_tx, stats_rx) = watch::channel(Arc::new(
+        initial_stats.unwrap_or_else(PublishedStats::initial),
+    ));
+
+    if let Some(path) = cli.stats_cache_path.clone() {
+        tokio::spawn(stats_cache_writer(stats_rx.clone(), path));
+    }
+
+    if cli.emit_stdout {
+        tokio::spawn(stdout_emitter(stats_rx.clone()));
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = cli.sqlite_path.clone() {
+        info!(path = %path.display(), "Persisting stats to SQLite");
+        tokio::spawn(sqlite_writer(stats_rx.clone(), path));
+    }
+
+    let expired_on_arrival = Arc::new(AtomicU64::new(0));
+    if let Some(url) = cli.pushgateway_url.clone() {
+        info!(url = %url, "Pushing metrics to Pushgateway");
+        tokio::spawn(pushgateway_pusher(
+            stats_rx.clone(),
+            expired_on_arrival.clone(),
+            url,
+        ));
+    }
+
+    info!("Spawning data actor...");
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let data_actor_config = DataActorConfig {
+        expiration_secs: cli.expiration_secs,
+        max_ttl_secs: cli.max_ttl_secs,
+        min_reports_to_appear: cli.min_reports_to_appear,
+        percentiles: cli.percentiles,
+        percentile_mode: cli.percentile_mode,
+        expire_relative_to: cli.expire_relative_to,
+        window_mode: cli.window_mode,
+        prune_interval_ticks: (cli.prune_interval_secs as f64 / RECALC_INTERVAL.as_secs_f64())
+            .ceil() as u64,
+        per_second_dedup: cli.per_second_dedup,
+        exclude_zero_hashrate: cli.exclude_zero_hashrate,
+        avg_window_secs: cli.avg_window_secs,
+        temp_median_window: cli.temp_median_window,
+        max_reports_per_worker: cli.max_reports_per_worker_per_window,
+        max_reports_per_iteration: cli.max_reports_per_iteration,
+        incremental_stats: cli.incremental_stats,
+        incremental_resync_ticks: cli.incremental_resync_ticks,
+        healthy_within_secs: cli.healthy_within_secs,
+        interruption_gap_secs: cli.interruption_gap_secs,
+        temp_precision: cli.temp_precision,
+        temp_alert_threshold: cli.temp_alert_threshold,
+        pool_temp_alert_thresholds: cli
+            .pool_temp_alert_thresholds
+            .iter()
+            .map(|(pool, threshold)| (cli.normalize_pool_case.apply(pool.clone()), *threshold))
+            .collect(),
+        hashrate_floor: cli.hashrate_floor,
+        pool_hashrate_floors: cli
+            .pool_hashrate_floors
+            .iter()
+            .map(|(pool, floor)| (cli.normalize_pool_case.apply(pool.clone()), *floor))
+            .collect(),
+        show_empty_pools: cli.show_empty_pools,
+        retain_pool: cli.retain_pool_secs.map(Duration::from_secs),
+        publish_on_change: cli.publish_on_change,
+        publish_smoothing: cli.publish_smoothing,
+        max_pools_in_response: cli.max_pools_in_response,
+        output_profile: cli.output_profile,
+        hold_initial_snapshot: initial_stats_cached,
+    };
+    let data_actor_alive = Arc::new(AtomicBool::new(true));
+    let data_actor = tokio::spawn(supervise_data_actor(
+        tokio::spawn(data_actor(
+            report_rx,
+            command_rx,
+            stats_tx,
+            shutdown_rx.clone(),
+            data_actor_config.clone(),
+        )),
+        shutdown_rx,
+        data_actor_alive.clone(),
+    ));
+
+    let (debug_tx, _) = broadcast::channel(DEBUG_STREAM_CAPACITY);
+
+    let app_state = AppState {
+        report_tx,
+        ack_mode: cli.ack_mode,
+        report_response: cli.report_response,
+        command_tx,
+        stats_rx,
+        debug_tx,
+        max_batch_size: cli.max_batch_size,
+        default_pool: cli.default_pool.clone(),
+        pool_case: cli.normalize_pool_case,
+        trim_worker_id: cli.trim_worker_id,
+        lowercase_worker_id: cli.lowercase_worker_id,
+        max_pool_name_len: cli.max_pool_name_len,
+        max_worker_id_len: cli.max_worker_id_len,
+        expired_reports: cli.expired_reports,
+        expired_on_arrival,
+        reports_ingested: Arc::new(AtomicU64::new(0)),
+        data_actor_alive,
+        data_actor_config: Arc::new(data_actor_config),
+        config_json: serde_json::to_string(&EffectiveConfig {
+            bind_address: BIND_ADDR,
+            recalc_interval_secs: RECALC_INTERVAL.as_secs_f64(),
+            cli: &cli,
+        })?
+        .into(),
+        shutdown_requested: Arc::new(Notify::new()),
+    };
+
+    if let Some(path) = cli.replay.clone() {
+        info!(path = %path.display(), speed = ?cli.replay_speed, "Replaying reports");
+        tokio::spawn(replay_reports(app_state.clone(), path, cli.replay_speed));
+    }
+
+    let report_route = if cli.lenient_content_type {
+        post(post_report_lenient)
+    } else {
+        post(post_report)
+    };
+
+    // Endpoints clients push to. With --internal-addr they are the only ones served
+    // on the public listener.
+    let mut ingest = Router::new()
+        .route("/report", report_route)
+        .route("/reports", post(post_reports))
+        .route("/preaggregated", post(post_preaggregated))
+        .route("/report/ws", get(report_ws));
+
+    let mut internal = Router::new()
+        .route("/stats", get(get_stats))
+        .route("/stats/by-region", get(get_stats_by_region))
+        .route("/stats/download", get(download_stats))
+        .route("/summary", get(get_summary))
+        .route("/status", get(get_status))
+        .route("/stats/{pool}/histogram", get(get_hashrate_histogram))
+        .route("/pools", get(get_pools))
+        .route("/worker/{worker_id}", get(get_worker))
+        .route("/metrics", get(get_metrics))
+        .route("/config", get(get_config))
+        .route("/info", get(get_info));
+
+    if let Some(tokens) = &auth_tokens {
+        ingest = ingest.route_layer(middleware::from_fn_with_state(
+            (tokens.clone(), Scope::Write),
+            require_scope,
+        ));
+        internal = internal.route_layer(middleware::from_fn_with_state(
+            (tokens.clone(), Scope::Read),
+            require_scope,
+        ));
+    }
+    // Added after the auth layer, as probes can't present a token.
+    internal = internal.route("/readyz", get(readyz));
+
+    match &cli.admin_token {
+        Some(Secret(token)) => {
+            let auth = middleware::from_fn_with_state(
+                Arc::<str>::from(token.as_str()),
+                require_admin_token,
+            );
+            let admin = Router::new()
+                .route("/pools/{pool}/disable", post(disable_pool))
+                .route("/pools/{pool}/enable", post(enable_pool))
+                .route("/reset", post(reset_stats))
+                .route("/shutdown", post(request_shutdown))
+                .route_layer(auth.clone());
+            let debug = Router::new()
+                .route("/stream", get(debug_stream))
+                .route_layer(auth);
+            internal = internal.nest("/admin", admin).nest("/debug", debug);
+        }
+        None => warn!("No --admin-token given, /admin and /debug endpoints are disabled"),
+    }
+
+    let finish = |router: Router<AppState>| {
+        let router = router.fallback(not_found).with_state(app_state.clone());
+        if !cli.access_log {
+            return router;
+        }
+        router.layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::DEBUG))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::DEBUG)
+                        .latency_unit(LatencyUnit::Micros),
+                ),
+        )
+    };
+
+    let header_read_timeout = Duration::from_secs(cli.header_read_timeout_secs);
+    let shutdown = shutdown_signal(app_state.shutdown_requested.clone()).shared();
+
+    #[cfg(feature = "grpc")]
+    let grpc_server = match cli.grpc_addr {
+        Some(grpc_addr) => {
+            info!("gRPC server listening on http://{}", grpc_addr);
+            let incoming = tonic::transport::server::TcpIncoming::bind(grpc_addr)?;
+            let service = grpc::MinerReportsServer::new(grpc::MinerReportsService::new(
+                app_state.clone(),
+                auth_tokens.clone(),
+            ));
+            Some(tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_incoming_shutdown(incoming, shutdown.clone()),
+            ))
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "mqtt")]
+    let mqtt_subscriber = cli.mqtt_broker.clone().zip(cli.mqtt_topic.clone()).map(
+        |(broker, topic)| {
+            info!(broker = %format!("{}:{}", broker.0, broker.1), topic, "Subscribing to MQTT reports");
+            tokio::spawn(mqtt::run(app_state.clone(), broker, topic, shutdown.clone()))
+        },
+    );
+
+    info!("Server listening on http://{}", BIND_ADDR);
+    let listener = TcpListener::bind(BIND_ADDR).await?;
+    match cli.internal_addr {
+        None => {
+            let app = finish(ingest.merge(internal));
+            serve(
+                listener,
+                app,
+                cli.max_connections,
+                header_read_timeout,
+                shutdown,
+            )
+            .await;
+        }
+        Some(internal_addr) => {
+            info!("Internal endpoints listening on http://{}", internal_addr);
+            let internal_listener = TcpListener::bind(internal_addr).await?;
+            // Each listener gets its own --max-connections budget, so a flood of
+            // clients on the public port can't lock operators out.
+            tokio::join!(
+                serve(
+                    listener,
+                    finish(ingest),
+                    cli.max_connections,
+                    header_read_timeout,
+                    shutdown.clone(),
+                ),
+                serve(
+                    internal_listener,
+                    finish(internal),
+                    cli.max_connections,
+                    header_read_timeout,
+                    shutdown,
+                ),
+            );
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_server) = grpc_server {
+        grpc_server.await??;
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt_subscriber) = mqtt_subscriber {
+        mqtt_subscriber.await?;
+    }
+
+    // The server no longer accepts reports, so the data actor can drain and stop.
+    shutdown_tx.send(true).ok();
+    data_actor.await??;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(worker_id: &str, timestamp: u64) -> Report {
+        Report {
+            worker_id: worker_id.to_string(),
+            pool: Some("pool".to_string()),
+            hashrate: 100.0,
+            temperature: 60.0,
+            timestamp,
+            ttl_secs: None,
+            power: None,
+            region: None,
+            received_at: None,
+            batch: None,
+        }
+    }
+
+    fn config() -> DataActorConfig {
+        DataActorConfig {
+            expiration_secs: 300,
+            max_ttl_secs: 3600,
+            min_reports_to_appear: 1,
+            percentiles: true,
+            percentile_mode: PercentileMode::Exact,
+            expire_relative_to: ExpireRelativeTo::Server,
+            window_mode: WindowMode::Sliding,
+            prune_interval_ticks: 1,
+            per_second_dedup: false,
+            exclude_zero_hashrate: false,
+            avg_window_secs: None,
+            temp_median_window: None,
+            max_reports_per_worker: None,
+            max_reports_per_iteration: NonZeroUsize::new(256).unwrap(),
+            incremental_stats: false,
+            incremental_resync_ticks: 60,
+            healthy_within_secs: 60,
+            interruption_gap_secs: None,
+            temp_precision: 1,
+            temp_alert_threshold: None,
+            pool_temp_alert_thresholds: HashMap::new(),
+            hashrate_floor: None,
+            pool_hashrate_floors: HashMap::new(),
+            show_empty_pools: true,
+            retain_pool: None,
+            publish_on_change: false,
+            publish_smoothing: None,
+            max_pools_in_response: None,
+            output_profile: OutputProfile::Default,
+            hold_initial_snapshot: false,
+        }
+    }
+
+    fn now_ts() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// A running `data_actor`, fed and watched the way the handlers do.
+    struct TestActor {
+        report_tx: mpsc::Sender<QueuedReport>,
+        stats_rx: watch::Receiver<Arc<PublishedStats>>,
+        command_tx: mpsc::Sender<DataActorCommand>,
+        // Held so the actor's shutdown branch stays idle.
+        _shutdown_tx: watch::Sender<bool>,
+    }
+
+    impl TestActor {
+        fn spawn(config: DataActorConfig) -> Self {
+            let (report_tx, report_rx) = mpsc::channel(1024);
+            let (command_tx, command_rx) = mpsc::channel(16);
+            let (stats_tx, stats_rx) = watch::channel(Arc::new(PublishedStats::initial()));
+            let (shutdown_tx, shutdown_rx) = watch::channel(false);
+            tokio::spawn(data_actor(
+                report_rx,
+                command_rx,
+                stats_tx,
+                shutdown_rx,
+                config,
+            ));
+            Self {
+                report_tx,
+                stats_rx,
+                command_tx,
+                _shutdown_tx: shutdown_tx,
+            }
+        }
+
+        async fn send(&self, report: Report) {
+            let queued = QueuedReport {
+                report,
+                processed_tx: None,
+            };
+            self.report_tx.send(queued).await.unwrap();
+        }
+
+        async fn command<T>(
+            &self,
+            command: impl FnOnce(oneshot::Sender<T>) -> DataActorCommand,
+        ) -> T {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.command_tx.send(command(reply_tx)).await.unwrap();
+            reply_rx.await.unwrap()
+        }
+
+        /// The next published snapshot, or `None` if there is none `within`.
+        async fn next_publish(&mut self, within: Duration) -> Option<AllStats> {
+            tokio::time::timeout(within, self.stats_rx.changed())
+                .await
+                .ok()?
+                .unwrap();
+            Some(self.stats_rx.borrow_and_update().stats.clone())
+        }
+
+        /// Skips snapshots until one `matches`, failing after `within`.
+        async fn publish_where(
+            &mut self,
+            within: Duration,
+            matches: impl Fn(&AllStats) -> bool,
+        ) -> AllStats {
+            tokio::time::timeout(within, async {
+                loop {
+                    let stats = self
+                        .next_publish(within)
+                        .await
+                        .expect("no snapshot published");
+                    if matches(&stats) {
+                        return stats;
+                    }
+                }
+            })
+            .await
+            .expect("no matching snapshot published")
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_on_change_skips_ticks_over_unchanged_reports() {
+        let mut actor = TestActor::spawn(DataActorConfig {
+            publish_on_change: true,
+            ..config()
+        });
+        // Already a few seconds old, so its age is never 0.
+        actor.send(report("a", now_ts() - 10)).await;
+        let stats = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.contains_key("pool")
+            })
+            .await;
+        assert_eq!(stats.pools["pool"].workers, 1);
+        assert!(stats.pools["pool"].reports_per_sec > 0.0);
+        assert!(stats.total.reports_per_sec > 0.0);
+
+        // Ingest has stopped, so the rates drop to 0 on the next tick, which still
+        // has to be published.
+        let stats = actor.next_publish(Duration::from_secs(2)).await.unwrap();
+        assert_eq!(stats.pools["pool"].reports_per_sec, 0.0);
+        assert_eq!(stats.pools["pool"].workers_joined, 0);
+        assert_eq!(stats.total.reports_per_sec, 0.0);
+
+        // The ticks after that only move the report's age.
+        assert!(
+            actor
+                .next_publish(Duration::from_millis(2_500))
+                .await
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn tumbling_window_boundary_carries_reports_stamped_after_it() {
+        let config = DataActorConfig {
+            window_mode: WindowMode::Tumbling,
+            expiration_secs: 60,
+            incremental_stats: true,
+            ..config()
+        };
+        assert_eq!(tumbling_window_start(119, &config), 60);
+        assert_eq!(tumbling_window_start(120, &config), 120);
+
+        let mut pools_data = HashMap::new();
+        let mut region_reports_since_tick = HashMap::new();
+        for timestamp in [100, 119, 120, 121] {
+            let queued = QueuedReport {
+                report: Report {
+                    received_at: Some(Instant::now()),
+                    ..report("a", timestamp)
+                },
+                processed_tx: None,
+            };
+            ingest_report(
+                &mut pools_data,
+                &mut region_reports_since_tick,
+                &config,
+                queued,
+            );
+        }
+        let pool_data = pools_data.get_mut("pool").unwrap();
+
+        let carried = pool_data.split_off_next_window(120, &config);
+        let timestamps = |reports: &mut dyn Iterator<Item = &Report>| {
+            reports.map(|report| report.timestamp).collect::<Vec<_>>()
+        };
+        assert_eq!(timestamps(&mut carried.iter()), [120, 121]);
+        assert_eq!(timestamps(&mut pool_data.reports.iter()), [100, 119]);
+        assert_eq!(pool_data.totals.pool.reports, 2);
+        assert_eq!(pool_data.reports_since_tick, 2);
+
+        pool_data.reports_since_tick = 0;
+        pool_data.received_since_tick = ReceivedTimes::default();
+        pool_data.start_next_window(carried, &config);
+        assert_eq!(timestamps(&mut pool_data.reports.iter()), [120, 121]);
+        assert_eq!(pool_data.totals.pool.reports, 2);
+        assert_eq!(pool_data.reports_since_tick, 2);
+        assert_eq!(pool_data.received_since_tick.count, 2);
+    }
+
+    #[tokio::test]
+    async fn tumbling_window_publishes_its_reports_once_it_closes() {
+        let mut actor = TestActor::spawn(DataActorConfig {
+            window_mode: WindowMode::Tumbling,
+            expiration_secs: 2,
+            ..config()
+        });
+        actor.send(report("a", now_ts())).await;
+
+        // Startup doesn't close a window, so nothing is published before this one.
+        let stats = actor.next_publish(Duration::from_secs(5)).await.unwrap();
+        let stats = if stats
+            .pools
+            .get("pool")
+            .is_some_and(|pool| pool.workers == 1)
+        {
+            stats
+        } else {
+            // The report was stamped just past the boundary and went to the next one.
+            actor.next_publish(Duration::from_secs(5)).await.unwrap()
+        };
+        assert_eq!(stats.pools["pool"].workers, 1);
+        assert_eq!(stats.pools["pool"].sum_hashrate, 100.0);
+
+        let stats = actor.next_publish(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stats.pools["pool"].workers, 0);
+    }
+
+    #[tokio::test]
+    async fn retained_pool_publishes_its_last_stats_as_stale() {
+        let mut actor = TestActor::spawn(DataActorConfig {
+            retain_pool: Some(Duration::from_secs(60)),
+            temp_alert_threshold: Some(50.0),
+            ..config()
+        });
+        actor
+            .send(Report {
+                ttl_secs: Some(1),
+                ..report("a", now_ts())
+            })
+            .await;
+
+        let fresh = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats
+                    .pools
+                    .get("pool")
+                    .is_some_and(|pool| pool.workers == 1)
+            })
+            .await;
+        assert!(!fresh.pools["pool"].stale);
+        assert!(fresh.pools["pool"].temp_alerting);
+
+        // Once the report expires, the pool keeps its last stats, alerts included.
+        let stale = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.get("pool").is_some_and(|pool| pool.stale)
+            })
+            .await;
+        let pool = &stale.pools["pool"];
+        assert_eq!(pool.workers, 1);
+        assert_eq!(pool.sum_hashrate, 100.0);
+        assert_eq!(pool.reports_per_sec, 0.0);
+        assert!(pool.temp_alerting);
+        assert_eq!(stale.total.pool_workers, 0);
+    }
+
+    #[tokio::test]
+    async fn reset_keeps_disabled_pools_disabled() {
+        let mut actor = TestActor::spawn(config());
+        actor.send(report("a", now_ts())).await;
+        actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.contains_key("pool")
+            })
+            .await;
+
+        let found = actor
+            .command(|reply_tx| DataActorCommand::SetPoolEnabled {
+                pool: "pool".to_string(),
+                enabled: false,
+                reply_tx,
+            })
+            .await;
+        assert!(found);
+        actor
+            .command(|reply_tx| DataActorCommand::Reset { reply_tx })
+            .await;
+
+        actor.send(report("a", now_ts())).await;
+        actor
+            .send(Report {
+                pool: Some("other".to_string()),
+                ..report("a", now_ts())
+            })
+            .await;
+        let stats = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.contains_key("other")
+            })
+            .await;
+        assert!(!stats.pools.contains_key("pool"));
+    }
+
+    #[tokio::test]
+    async fn hashrate_share_counts_each_worker_once() {
+        let mut actor = TestActor::spawn(config());
+        let now_ts = now_ts();
+        for timestamp in now_ts - 4..=now_ts {
+            actor.send(report("a", timestamp)).await;
+        }
+        actor
+            .send(Report {
+                pool: Some("other".to_string()),
+                ..report("b", now_ts)
+            })
+            .await;
+
+        // "pool" has five times the reports but the same single worker at 100.
+        let stats = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.contains_key("pool") && stats.pools.contains_key("other")
+            })
+            .await;
+        assert_eq!(stats.pools["pool"].sum_hashrate, 500.0);
+        assert_eq!(stats.pools["pool"].hashrate_share_pct, 50.0);
+        assert_eq!(stats.pools["other"].hashrate_share_pct, 50.0);
+    }
+
+    #[tokio::test]
+    async fn alerts_are_evaluated_against_the_smoothed_stats() {
+        let mut actor = TestActor::spawn(DataActorConfig {
+            publish_smoothing: Some(0.5),
+            temp_alert_threshold: Some(70.0),
+            ..config()
+        });
+        actor.send(report("a", now_ts())).await;
+        actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats.pools.contains_key("pool")
+            })
+            .await;
+
+        // The raw average of 80 is over the threshold, the published 70 is not.
+        actor
+            .send(Report {
+                temperature: 100.0,
+                ..report("b", now_ts())
+            })
+            .await;
+        let stats = actor
+            .publish_where(Duration::from_secs(5), |stats| {
+                stats
+                    .pools
+                    .get("pool")
+                    .is_some_and(|pool| pool.workers == 2)
+            })
+            .await;
+        assert_eq!(stats.pools["pool"].avg_temp, 70.0);
+        assert!(!stats.pools["pool"].temp_alerting);
+
+        let stats = actor.next_publish(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stats.pools["pool"].avg_temp, 75.0);
+        assert!(stats.pools["pool"].temp_alerting);
+    }
+
+    #[test]
+    fn max_timestamp_is_clamped_to_the_allowed_skew() {
+        let now_ts = 1_700_000_000;
+        assert_eq!(
+            clamp_timestamp(u64::MAX, now_ts),
+            now_ts + MAX_FUTURE_SKEW_SECS
+        );
+        assert_eq!(clamp_timestamp(now_ts - 10, now_ts), now_ts - 10);
+        assert_eq!(clamp_timestamp(u64::MAX, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn max_timestamp_does_not_overflow_the_stats() {
+        let config = config();
+        let far_future = report("a", u64::MAX);
+        assert_eq!(far_future.expires_at(&config), u64::MAX);
+
+        let reports = VecDeque::from([report("a", 0), far_future, report("b", 1_000)]);
+        let mut previous_workers = HashSet::new();
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_000,
+            &mut previous_workers,
+            &config,
+        );
+
+        assert_eq!(stats.workers, 2);
+        assert_eq!(stats.oldest_report_age_secs, Some(1_000));
+        assert_eq!(stats.max_report_interval_secs, Some(u64::MAX - 1_000));
+        assert_eq!(stats.healthy_worker_pct, Some(100.0));
+    }
+
+    #[test]
+    fn single_report_has_well_defined_spread() {
+        let config = config();
+        let reports = VecDeque::from([report("a", 1_000)]);
+        let mut previous_workers = HashSet::new();
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_000,
+            &mut previous_workers,
+            &config,
+        );
+
+        assert_eq!(stats.workers, 1);
+        assert_eq!(stats.avg_temp, 60.0);
+        assert_eq!(stats.median_temp, Some(60.0));
+        assert_eq!(stats.p95_temp, Some(60.0));
+        assert_eq!(stats.p99_temp, Some(60.0));
+        assert_eq!(stats.avg_report_interval_secs, Some(0.0));
+        assert_eq!(stats.max_report_interval_secs, Some(0));
+    }
+
+    #[test]
+    fn single_worker_percentiles_span_its_reports() {
+        let config = config();
+        let mut reports = VecDeque::from([report("a", 1_000), report("a", 1_010)]);
+        reports[1].temperature = 70.0;
+        let mut previous_workers = HashSet::new();
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_010,
+            &mut previous_workers,
+            &config,
+        );
+
+        assert_eq!(stats.workers, 1);
+        assert_eq!(stats.median_temp, Some(60.0));
+        assert_eq!(stats.p99_temp, Some(70.0));
+        assert_eq!(stats.avg_report_interval_secs, Some(10.0));
+    }
+
+    #[test]
+    fn preaggregated_batch_weighs_as_its_reports() {
+        let config = config();
+        // Three reports from two workers averaging 200 H/s and 50 degrees, next to a
+        // raw report of 100 H/s and 60 degrees.
+        let mut batch = report("preaggregated:site", 1_000);
+        batch.hashrate = 200.0;
+        batch.temperature = 50.0;
+        batch.batch = Some(Batch {
+            reports: 3,
+            workers: 2,
+        });
+        let reports = VecDeque::from([batch, report("a", 1_000)]);
+        let mut previous_workers = HashSet::new();
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_000,
+            &mut previous_workers,
+            &config,
+        );
+
+        assert_eq!(stats.workers, 3);
+        assert_eq!(stats.sum_hashrate, 700.0);
+        assert_eq!(stats.avg_hashrate, 175.0);
+        assert_eq!(stats.avg_temp, 52.5);
+
+        let mut totals = PoolTotals::from_reports(reports.iter());
+        assert_eq!(totals.pool.reports, 4);
+        assert_eq!(totals.pool.hashrate, 700.0);
+        assert_eq!(totals.regions[reports[0].region()].reports, 4);
+        totals.remove(&reports[0]);
+        assert_eq!(totals.pool.reports, 1);
+        assert_eq!(totals.pool.hashrate, 100.0);
+        totals.remove(&reports[1]);
+        assert!(totals.regions.is_empty());
+    }
+
+    #[test]
+    fn temp_median_window_hides_a_single_glitch() {
+        let config = DataActorConfig {
+            temp_median_window: Some(3),
+            ..config()
+        };
+        let mut reports: VecDeque<Report> = (0..4).map(|i| report("a", 1_000 + i)).collect();
+        reports[2].temperature = 255.0;
+        let mut previous_workers = HashSet::new();
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_003,
+            &mut previous_workers,
+            &config,
+        );
+
+        assert_eq!(stats.avg_temp, 60.0);
+        assert_eq!(stats.p99_temp, Some(255.0));
+    }
+
+    #[test]
+    fn temp_median_window_hides_a_zero_glitch_even_first() {
+        let config = DataActorConfig {
+            temp_median_window: Some(3),
+            ..config()
+        };
+        for glitch in [0, 2] {
+            let mut reports: VecDeque<Report> = (0..4).map(|i| report("a", 1_000 + i)).collect();
+            reports[glitch].temperature = 0.0;
+            let stats = compute_pool_stats(
+                "pool",
+                reports.iter(),
+                0.0,
+                1_003,
+                &mut HashSet::new(),
+                &config,
+            );
+            assert_eq!(stats.avg_temp, 60.0, "glitch in report {glitch}");
+        }
+
+        // Too few readings to tell a glitch apart; they count as they are.
+        let mut reports = VecDeque::from([report("a", 1_000), report("a", 1_001)]);
+        reports[0].temperature = 0.0;
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_001,
+            &mut HashSet::new(),
+            &config,
+        );
+        assert_eq!(stats.avg_temp, 30.0);
+    }
+
+    #[test]
+    fn filtering_out_every_pool_leaves_an_empty_snapshot() {
+        let mut stats = AllStats {
+            generated_at: 1_000,
+            ..AllStats::default()
+        };
+        stats.pools.insert("pool".to_string(), PoolStats::default());
+
+        let filtered = stats.filter_pools(|pool, _| pool == "missing");
+        let json = serde_json::to_value(&filtered).unwrap();
+        assert_eq!(json["pools"], serde_json::json!({}));
+        assert_eq!(json["generated_at"], 1_000);
+        assert!(json["total"].is_object());
+        assert!(json.get("truncated").is_none());
+
+        let legacy = serde_json::to_value(LegacyAllStats::from(&filtered)).unwrap();
+        assert_eq!(legacy["pools"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn hashrates_are_humanized_with_si_suffixes() {
+        assert_eq!(format_hashrate(0.0), "0.00 H/s");
+        assert_eq!(format_hashrate(950.0), "950 H/s");
+        assert_eq!(format_hashrate(1_500.0), "1.50 KH/s");
+        assert_eq!(format_hashrate(12_345_000_000_000.0), "12.3 TH/s");
+        assert_eq!(format_hashrate(2.5e18), "2500 PH/s");
+    }
+
+    #[test]
+    fn single_report_tdigest_quantiles_equal_its_temperature() {
+        let config = DataActorConfig {
+            percentile_mode: PercentileMode::Tdigest,
+            ..config()
+        };
+        let mut digests = TempDigests::default();
+        digests.add(&report("a", 1_000));
+        digests.update(&config, Some(1_000));
+
+        assert_eq!(digests.quantiles(), Some((60.0, 60.0, 60.0)));
+    }
+
+    #[test]
+    fn per_second_dedup_keeps_the_last_report_of_each_worker_and_second() {
+        let mut reports =
+            VecDeque::from([report("a", 1_000), report("a", 1_000), report("b", 1_000)]);
+        reports[1].hashrate = 300.0;
+        let stats = |config: &DataActorConfig| {
+            compute_pool_stats(
+                "pool",
+                reports.iter(),
+                0.0,
+                1_000,
+                &mut HashSet::new(),
+                config,
+            )
+        };
+
+        let deduped = stats(&DataActorConfig {
+            per_second_dedup: true,
+            ..config()
+        });
+        assert_eq!(deduped.workers, 2);
+        assert_eq!(deduped.sum_hashrate, 400.0);
+        assert_eq!(deduped.avg_hashrate, 200.0);
+
+        assert_eq!(stats(&config()).sum_hashrate, 500.0);
+    }
+
+    #[test]
+    fn per_worker_cap_keeps_each_workers_latest_reports() {
+        let mut reports: VecDeque<Report> = (0..3).map(|i| report("a", 1_000 + i)).collect();
+        for (report, hashrate) in reports.iter_mut().zip([100.0, 200.0, 300.0]) {
+            report.hashrate = hashrate;
+        }
+        reports.push_back(report("b", 1_000));
+        let stats = |config: &DataActorConfig| {
+            compute_pool_stats(
+                "pool",
+                reports.iter(),
+                0.0,
+                1_002,
+                &mut HashSet::new(),
+                config,
+            )
+        };
+
+        let capped = stats(&DataActorConfig {
+            max_reports_per_worker: NonZeroUsize::new(2),
+            ..config()
+        });
+        assert_eq!(capped.workers, 2);
+        assert_eq!(capped.sum_hashrate, 600.0);
+        assert_eq!(capped.avg_hashrate, 200.0);
+
+        assert_eq!(stats(&config()).sum_hashrate, 700.0);
+    }
+
+    #[test]
+    fn smoothing_blends_fractional_fields_and_keeps_counts() {
+        let previous = PoolStats {
+            workers: 4,
+            avg_hashrate: 100.0,
+            avg_temp: 60.0,
+            median_temp: None,
+            ..PoolStats::default()
+        };
+        let mut stats = PoolStats {
+            workers: 2,
+            avg_hashrate: 200.0,
+            avg_temp: 70.0,
+            median_temp: Some(70.0),
+            ..PoolStats::default()
+        };
+        stats.smooth(&previous, 0.25, 1);
+
+        assert_eq!(stats.avg_hashrate, 125.0);
+        assert_eq!(stats.avg_temp, 62.5);
+        assert_eq!(stats.workers, 2);
+        assert_eq!(stats.median_temp, Some(70.0));
+    }
+
+    #[test]
+    fn mostly_idle_pool_trips_the_hashrate_floor_without_zeros_in_the_average() {
+        let config = DataActorConfig {
+            exclude_zero_hashrate: true,
+            hashrate_floor: Some(500.0),
+            ..config()
+        };
+        let mut reports: VecDeque<Report> = (0..10)
+            .map(|i| Report {
+                hashrate: 0.0,
+                ..report(&format!("idle-{i}"), 1_000)
+            })
+            .collect();
+        reports[0].hashrate = 100.0;
+        let mut stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_000,
+            &mut HashSet::new(),
+            &config,
+        );
+
+        assert_eq!(stats.workers, 10);
+        assert_eq!(stats.avg_hashrate, 100.0);
+        assert_eq!(stats.pool_hashrate, 100.0);
+        PoolAlerts::default().evaluate("pool", &mut stats, &config);
+        assert!(stats.hashrate_alerting);
+    }
+
+    #[test]
+    fn incremental_stats_keeps_reports_in_expiry_order() {
+        let config = DataActorConfig {
+            incremental_stats: true,
+            ..config()
+        };
+        let mut pools_data = HashMap::new();
+        let mut region_reports_since_tick = HashMap::new();
+        for (worker_id, timestamp, ttl_secs) in
+            [("a", 100, None), ("b", 110, Some(30)), ("c", 120, None)]
+        {
+            let queued = QueuedReport {
+                report: Report {
+                    ttl_secs,
+                    ..report(worker_id, timestamp)
+                },
+                processed_tx: None,
+            };
+            ingest_report(
+                &mut pools_data,
+                &mut region_reports_since_tick,
+                &config,
+                queued,
+            );
+        }
+
+        let worker_ids = pools_data["pool"]
+            .reports
+            .iter()
+            .map(|report| report.worker_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(worker_ids, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn max_temp_is_the_hottest_report() {
+        let reports = [
+            report("a", 100),
+            Report {
+                temperature: 85.0,
+                ..report("b", 100)
+            },
+            Report {
+                temperature: 70.0,
+                ..report("b", 101)
+            },
+        ];
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            101,
+            &mut HashSet::new(),
+            &config(),
+        );
+
+        assert_eq!(stats.max_temp, Some(85.0));
+        assert_eq!(stats.avg_temp, 71.7);
+    }
+
+    #[test]
+    fn summary_leaves_stale_pools_out() {
+        let live = PoolStats {
+            workers: 2,
+            pool_hashrate: 200.0,
+            max_temp: Some(70.0),
+            ..PoolStats::default()
+        };
+        let stale = PoolStats {
+            stale: true,
+            pool_hashrate: 500.0,
+            max_temp: Some(90.0),
+            ..live.clone()
+        };
+        let stats = AllStats {
+            pools: BTreeMap::from([("live".to_string(), live), ("stale".to_string(), stale)]),
+            total: TotalStats {
+                workers: 2,
+                ..TotalStats::default()
+            },
+            ..AllStats::default()
+        };
+
+        let summary = Summary::new(&stats);
+        assert_eq!(summary.pools, 1);
+        assert_eq!(summary.workers, 2);
+        assert_eq!(summary.total_hashrate, 200.0);
+        assert_eq!(summary.max_temp, Some(70.0));
+    }
+
+    fn form_request(body: &'static str) -> Request {
+        Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// An `AppState` with the CLI defaults, plus the data actor's end of the queue.
+    fn app_state() -> (AppState, mpsc::Receiver<QueuedReport>) {
+        let (report_tx, report_rx) = mpsc::channel(16);
+        let (command_tx, _) = mpsc::channel(1);
+        let (_, stats_rx) = watch::channel(Arc::new(PublishedStats::initial()));
+        let state = AppState {
+            report_tx,
+            ack_mode: AckMode::Queued,
+            report_response: ReportResponse::Empty,
+            command_tx,
+            stats_rx,
+            debug_tx: broadcast::channel(1).0,
+            max_batch_size: 10_000,
+            default_pool: None,
+            pool_case: PoolCase::None,
+            trim_worker_id: false,
+            lowercase_worker_id: false,
+            max_pool_name_len: 128,
+            max_worker_id_len: 128,
+            expired_reports: ExpiredReports::Reject,
+            expired_on_arrival: Arc::default(),
+            reports_ingested: Arc::default(),
+            data_actor_alive: Arc::new(AtomicBool::new(true)),
+            data_actor_config: Arc::new(config()),
+            config_json: "{}".into(),
+            shutdown_requested: Arc::default(),
+        };
+        (state, report_rx)
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn form_report_parses_its_fields() {
+        let report = parse_form_report(form_request(
+            "worker_id=a&pool=p&hashrate=12.5&temperature=60&timestamp=1700000000",
+        ))
+        .await
+        .unwrap();
+        assert_eq!(report.worker_id, "a");
+        assert_eq!(report.pool.as_deref(), Some("p"));
+        assert_eq!(report.hashrate, 12.5);
+        assert_eq!(report.timestamp, 1_700_000_000);
+    }
+
+    #[tokio::test]
+    async fn form_report_with_an_empty_pool_has_none() {
+        let report = parse_form_report(form_request(
+            "worker_id=a&pool=&hashrate=1&temperature=60&timestamp=1700000000",
+        ))
+        .await
+        .unwrap();
+        assert_eq!(report.pool, None);
+    }
+
+    #[tokio::test]
+    async fn malformed_form_report_gets_a_json_error() {
+        let response = parse_form_report(form_request(
+            "worker_id=a&hashrate=fast&temperature=60&timestamp=1700000000",
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = json_body(response).await;
+        assert!(body["error"].as_str().unwrap().contains("hashrate"));
+    }
+
+    #[tokio::test]
+    async fn invalid_timestamp_gets_its_own_error_code() {
+        let body = br#"{"worker_id":"a","pool":"p","hashrate":1,"temperature":60,"timestamp":"yesterday"}"#;
+        let Json(report) = Json::<Report<WireTimestamp>>::from_bytes(body).unwrap();
+        let error = report.validate().unwrap_err();
+        assert!(error.contains("yesterday"));
+
+        let response = invalid_timestamp_response(&error);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["code"], "invalid_timestamp");
+
+        // Everywhere else it is still a deserialization error.
+        assert!(serde_json::from_slice::<Report>(body).is_err());
+        let body = br#"{"worker_id":"a","hashrate":1,"temperature":60,"timestamp":"2023-11-14T22:13:20Z"}"#;
+        let Json(report) = Json::<Report<WireTimestamp>>::from_bytes(body).unwrap();
+        assert_eq!(report.validate().unwrap().timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn avg_window_only_averages_recent_reports() {
+        let config = DataActorConfig {
+            avg_window_secs: Some(10),
+            ..config()
+        };
+        let reports = [
+            Report {
+                hashrate: 300.0,
+                temperature: 90.0,
+                ..report("a", 980)
+            },
+            report("b", 995),
+        ];
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            1_000,
+            &mut HashSet::new(),
+            &config,
+        );
+
+        assert_eq!(stats.sum_hashrate, 400.0);
+        assert_eq!(stats.workers, 2);
+        assert_eq!(stats.avg_hashrate, 100.0);
+        assert_eq!(stats.avg_temp, 60.0);
+        // "a" went quiet before the window, so it doesn't count as mining.
+        assert_eq!(stats.pool_hashrate, 100.0);
+
+        let stats = compute_pool_stats(
+            "pool",
+            reports.iter(),
+            0.0,
+            2_000,
+            &mut HashSet::new(),
+            &config,
+        );
+        assert_eq!(stats.sum_hashrate, 400.0);
+        assert_eq!(stats.avg_hashrate, 0.0);
+        assert_eq!(stats.avg_temp, 0.0);
+        assert_eq!(stats.pool_hashrate, 0.0);
+    }
+
+    #[test]
+    fn pool_is_interrupted_by_a_gap_longer_than_the_threshold() {
+        let config = DataActorConfig {
+            interruption_gap_secs: Some(30),
+            ..config()
+        };
+        let interrupted = |gap: u64| {
+            let reports = [report("a", 100), report("b", 100 + gap)];
+            let stats = compute_pool_stats(
+                "pool",
+                reports.iter(),
+                0.0,
+                200,
+                &mut HashSet::new(),
+                &config,
+            );
+            assert_eq!(stats.max_report_interval_secs, Some(gap));
+            stats.interrupted
+        };
+
+        assert!(!interrupted(29));
+        assert!(!interrupted(30));
+        assert!(interrupted(31));
+    }
+
+    #[tokio::test]
+    async fn verbose_report_response_shows_the_normalized_pool() {
+        let (state, mut report_rx) = app_state();
+        let state = AppState {
+            report_response: ReportResponse::Verbose,
+            default_pool: Some("Main".to_string()),
+            pool_case: PoolCase::Lower,
+            ..state
+        };
+        let response = accept_report(
+            &state,
+            Report {
+                pool: None,
+                ..report("a", now_ts())
+            },
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["accepted"], true);
+        assert_eq!(body["pool"], "main");
+        assert_eq!(report_rx.recv().await.unwrap().report.pool(), "main");
+
+        let state = AppState {
+            report_response: ReportResponse::Empty,
+            ..state
+        };
+        let response = accept_report(&state, report("a", now_ts())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn preaggregated_batch_is_validated() {
+        let (state, _report_rx) = app_state();
+        let state = AppState {
+            max_batch_size: 100,
+            ..state
+        };
+        let post = |total_hashrate: f64, total_temp: f64, report_count: u64| {
+            post_preaggregated(
+                State(state.clone()),
+                Ok(Json(PreAggregatedReport {
+                    pool: "pool".to_string(),
+                    worker_count: 1,
+                    total_hashrate,
+                    total_temp,
+                    report_count,
+                    timestamp: WireTimestamp::Secs(now_ts()),
+                    collector: None,
+                })),
+            )
+        };
+
+        assert_eq!(post(100.0, 60.0, 100).await.status(), StatusCode::OK);
+        assert_eq!(
+            post(100.0, 60.0, 101).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post(f64::NAN, 60.0, 1).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post(100.0, f64::INFINITY, 1).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(post(-1.0, 60.0, 1).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn raw_reports_cant_take_a_preaggregated_worker_id() {
+        let (state, _report_rx) = app_state();
+        for worker_id in ["preaggregated:", " PreAggregated:edge"] {
+            let report = report(worker_id, now_ts());
+            assert!(check_report_limits(&state, &report).is_err());
+        }
+        assert!(check_report_limits(&state, &report("preaggregated", now_ts())).is_ok());
+    }
+
+    #[test]
+    fn metrics_include_the_expired_on_arrival_counter() {
+        let metrics = render_metrics(&AllStats::default(), 7);
+        assert!(
+            metrics
+                .lines()
+                .any(|line| line == "miner_reports_expired_on_arrival_total 7")
+        );
+    }
 }